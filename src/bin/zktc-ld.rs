@@ -0,0 +1,51 @@
+use anyhow::{Context, Result};
+use zktc_asm::{file, link, object, output};
+
+use clap::Parser;
+
+#[derive(Parser)]
+#[clap(version = "0.1", author = "kkinos", about = "ZKTC linker")]
+struct Args {
+    /// object file paths, or - for stdin
+    #[arg(required = true)]
+    file_paths: Vec<std::path::PathBuf>,
+
+    /// output file name, or - for stdout [default: a.mem, a.bin, ... by format]
+    #[arg(short = 'o')]
+    output_file_name: Option<std::path::PathBuf>,
+
+    #[command(flatten)]
+    output: output::OutputArgs,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    file::check_std(
+        args.file_paths.iter().map(|p| p.as_path()),
+        args.output_file_name
+            .iter()
+            .map(|p| p.as_path())
+            .chain(args.output.files()),
+    )?;
+
+    let mut objects = Vec::new();
+    for file_path in &args.file_paths {
+        let text = file::read(file_path)?;
+        let object = object::read(&text)
+            .with_context(|| format!("could not load object '{}'", file_path.display()))?;
+        objects.push(object);
+    }
+
+    let layout = args.output.layout.layout()?;
+    let (mut image, sections, label_table) = link::link(objects, &layout)?;
+    let name = file::module_name(&args.file_paths[0]);
+    output::write(
+        &mut image,
+        &sections,
+        &label_table,
+        &layout,
+        &name,
+        &args.output,
+        args.output_file_name.as_deref(),
+    )
+}
@@ -0,0 +1,215 @@
+use crate::format::entry;
+use crate::image::Image;
+use crate::parse::{Label, Line, LineKind};
+use anyhow::{anyhow, Result};
+use clap_num::maybe_hex;
+
+/// Bytes of the boot header: magic, length, entry and checksum, each a
+/// little endian word.
+pub const HEADER_SIZE: u16 = 8;
+
+#[derive(clap::ValueEnum, Debug, PartialEq, Clone, Copy)]
+pub enum Checksum {
+    /// 16 bit sum of the bytes
+    Sum16,
+    /// CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xffff)
+    Crc16Ccitt,
+    /// CRC-16/MODBUS (reflected polynomial 0x8005, initial value 0xffff)
+    Crc16Modbus,
+}
+
+impl Checksum {
+    pub fn compute(&self, bytes: &[u8]) -> u16 {
+        match self {
+            Checksum::Sum16 => bytes
+                .iter()
+                .fold(0u16, |sum, b| sum.wrapping_add(*b as u16)),
+            Checksum::Crc16Ccitt => {
+                let mut crc: u16 = 0xffff;
+                for byte in bytes {
+                    crc ^= (*byte as u16) << 8;
+                    for _ in 0..8 {
+                        crc = if crc & 0x8000 != 0 {
+                            crc << 1 ^ 0x1021
+                        } else {
+                            crc << 1
+                        };
+                    }
+                }
+                crc
+            }
+            Checksum::Crc16Modbus => {
+                let mut crc: u16 = 0xffff;
+                for byte in bytes {
+                    crc ^= *byte as u16;
+                    for _ in 0..8 {
+                        crc = if crc & 0x0001 != 0 {
+                            crc >> 1 ^ 0xa001
+                        } else {
+                            crc >> 1
+                        };
+                    }
+                }
+                crc
+            }
+        }
+    }
+}
+
+/// The bootloader header written into the image.
+#[derive(clap::Args)]
+pub struct BootArgs {
+    /// write a boot header (magic, length, entry, checksum) at this address
+    #[arg(long = "boot-header", value_parser=maybe_hex::<u16>)]
+    pub boot_header: Option<u16>,
+
+    /// magic word of the boot header
+    #[arg(long = "boot-magic", default_value_t = 0x4b5a, value_parser=maybe_hex::<u16>)]
+    pub boot_magic: u16,
+
+    /// checksum of the boot header
+    #[arg(long = "boot-checksum", value_enum, default_value_t = Checksum::Crc16Ccitt)]
+    pub boot_checksum: Checksum,
+}
+
+/// Writes the boot header at `--boot-header` into the image. The header
+/// covers the bytes from its end to the end of the image, with gaps read as
+/// `fill`, and `entry_symbol` is resolved like the S-record entry. The header
+/// may only replace bytes reserved with `.space` in `lines`.
+pub fn apply(
+    image: &mut Image,
+    label_table: &[Label],
+    lines: &[Line],
+    entry_symbol: Option<&str>,
+    fill: u8,
+    args: &BootArgs,
+) -> Result<()> {
+    let address = match args.boot_header {
+        Some(address) => address,
+        None => return Ok(()),
+    };
+    let start = address as u32 + HEADER_SIZE as u32;
+    if start > 0x10000 {
+        return Err(anyhow!(
+            "boot header at 0x{:04x} does not fit in memory",
+            address
+        ));
+    }
+    if let Some(line) = lines.iter().find(|l| {
+        (l.address as u32) < start
+            && (address as u32) < l.address as u32 + l.size as u32
+            && l.kind != LineKind::Space
+    }) {
+        return Err(anyhow!(
+            "boot header at 0x{:04x}-0x{:04x} overlaps '{}' at 0x{:04x}, reserve it with .space {}",
+            address,
+            start - 1,
+            line.text,
+            line.address,
+            HEADER_SIZE
+        ));
+    }
+    let end = image.end().max(start);
+    let bytes: Vec<u8> = (start..end)
+        .map(|a| image.byte(a).unwrap_or(fill))
+        .collect();
+    if bytes.len() > 0xffff {
+        return Err(anyhow!(
+            "image after the boot header is too long ({} bytes)",
+            bytes.len()
+        ));
+    }
+    let entry = entry(image, label_table, entry_symbol)?;
+
+    let mut header = Vec::new();
+    for word in [
+        args.boot_magic,
+        bytes.len() as u16,
+        entry,
+        args.boot_checksum.compute(&bytes),
+    ] {
+        header.extend(word.to_le_bytes());
+    }
+    image.write(address, &header);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gen::gen;
+    use crate::image::Segment;
+    use crate::layout::{place, Layout};
+    use crate::parse::parse;
+
+    #[test]
+    fn can_compute_checksum() {
+        let bytes = b"123456789";
+        assert_eq!(Checksum::Sum16.compute(bytes), 0x01dd);
+        assert_eq!(Checksum::Crc16Ccitt.compute(bytes), 0x29b1);
+        assert_eq!(Checksum::Crc16Modbus.compute(bytes), 0x4b37);
+    }
+
+    #[test]
+    fn can_apply_boot_header() -> Result<()> {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x0108,
+            bytes: b"1234".to_vec(),
+        });
+        image.push(Segment {
+            address: 0x010d,
+            bytes: b"6789".to_vec(),
+        });
+        let args = BootArgs {
+            boot_header: Some(0x0100),
+            boot_magic: 0x4b5a,
+            boot_checksum: Checksum::Crc16Ccitt,
+        };
+        apply(&mut image, &[], &[], None, b'5', &args)?;
+
+        assert_eq!(
+            image.segments,
+            vec![
+                Segment {
+                    address: 0x0100,
+                    bytes: vec![
+                        0x5a, 0x4b, 0x09, 0x00, 0x08, 0x01, 0xb1, 0x29, b'1', b'2', b'3', b'4'
+                    ],
+                },
+                Segment {
+                    address: 0x010d,
+                    bytes: b"6789".to_vec(),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cannot_overwrite_code_with_boot_header() -> Result<()> {
+        let args = BootArgs {
+            boot_header: Some(0x0000),
+            boot_magic: 0x4b5a,
+            boot_checksum: Checksum::Sum16,
+        };
+        let build = |text: &str| -> Result<(Image, Vec<Line>)> {
+            let (mut sections, mut label_table) = parse(text.to_string())?;
+            place(&Layout::new(0), &mut sections, &mut label_table)?;
+            let lines = sections.iter().flat_map(|s| s.lines.clone()).collect();
+            Ok((gen(sections, label_table)?, lines))
+        };
+
+        let (mut image, lines) = build("start:\n\tmov x1, x2\n\ttrap\n")?;
+        let err = apply(&mut image, &[], &lines, None, 0, &args).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "boot header at 0x0000-0x0007 overlaps 'mov x1, x2' at 0x0000, reserve it with .space 8"
+        );
+
+        let (mut image, lines) = build("\t.space 8\nstart:\n\ttrap\n")?;
+        apply(&mut image, &[], &lines, None, 0, &args)?;
+        assert_eq!(image.to_bytes(0)[..2], [0x5a, 0x4b]);
+        Ok(())
+    }
+}
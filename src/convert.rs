@@ -0,0 +1,140 @@
+use crate::file;
+use crate::format::{self, Format, FormatArgs};
+use crate::image::Image;
+use anyhow::{anyhow, Result};
+use clap_num::maybe_hex;
+use std::path::PathBuf;
+
+/// Options of `zktc-asm convert`.
+#[derive(clap::Args)]
+pub struct ConvertArgs {
+    /// image file, or - for stdin
+    pub input: PathBuf,
+
+    /// input format [default: by the extension of the input file]
+    #[arg(short = 'I', value_enum)]
+    pub input_format: Option<Format>,
+
+    /// address of the first byte of mem and binary input
+    #[arg(long = "load-address", default_value_t = 0, value_parser=maybe_hex::<u16>)]
+    pub load_address: u16,
+
+    /// keep only the bytes in START:END (END is exclusive)
+    #[arg(long = "range", value_parser = parse_range)]
+    pub range: Option<(u16, u32)>,
+
+    /// move the image by this many bytes (e.g. 0x8000 or -0x100)
+    #[arg(long = "offset", default_value_t = 0, allow_hyphen_values = true, value_parser = parse_offset)]
+    pub offset: i32,
+
+    /// output file name, or - for stdout [default: a.mem, a.bin, ... by format]
+    #[arg(short = 'o')]
+    pub output_file_name: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub format: FormatArgs,
+}
+
+fn parse_range(s: &str) -> Result<(u16, u32), String> {
+    let (start, end) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected start:end, got '{}'", s))?;
+    let start = maybe_hex::<u32>(start)?;
+    let end = maybe_hex::<u32>(end)?;
+    if start > 0xFFFF || start > end || end > 0x10000 {
+        return Err(format!("invalid range '{}'", s));
+    }
+    Ok((start as u16, end))
+}
+
+fn parse_offset(s: &str) -> Result<i32, String> {
+    match s.strip_prefix('-') {
+        Some(s) => maybe_hex::<u16>(s).map(|n| -(n as i32)),
+        None => maybe_hex::<u16>(s).map(|n| n as i32),
+    }
+}
+
+/// Moves every segment of the image by `offset` bytes.
+pub fn offset(image: &Image, offset: i32) -> Result<Image> {
+    let mut moved = Image::default();
+    for segment in &image.segments {
+        let address = segment.address as i32 + offset;
+        if address < 0 || address as u32 + segment.bytes.len() as u32 > 0x10000 {
+            return Err(anyhow!(
+                "segment at 0x{:04x} moved out of memory",
+                segment.address
+            ));
+        }
+        moved.write(address as u16, &segment.bytes);
+    }
+    Ok(moved)
+}
+
+/// Reads an image, extracts `--range`, applies `--offset` and writes it in the
+/// output format.
+pub fn convert(args: &ConvertArgs) -> Result<()> {
+    let input_format = match args.input_format {
+        Some(format) => format,
+        None => args
+            .input
+            .extension()
+            .and_then(|e| Format::from_extension(&e.to_string_lossy()))
+            .ok_or_else(|| anyhow!("unknown input format, use -I to select it"))?,
+    };
+    let data = file::read_bytes(&args.input)?;
+    let mut image = format::read(
+        &data,
+        input_format,
+        args.load_address,
+        args.format.endian,
+        args.format.word_width,
+    )?;
+    if let Some((start, end)) = args.range {
+        image = image.slice(start, end);
+    }
+    image = offset(&image, args.offset)?;
+
+    let output_file_name = args
+        .output_file_name
+        .clone()
+        .unwrap_or_else(|| format!("a.{}", args.format.format.extension()).into());
+    file::check_std([args.input.as_path()], [output_file_name.as_path()])?;
+    format::check_size(&image, &[], &args.format)?;
+    format::write_file(
+        &image,
+        &[],
+        &[],
+        &file::module_name(&args.input),
+        &args.format,
+        &output_file_name,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::Segment;
+
+    #[test]
+    fn can_offset_image() -> Result<()> {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x0100,
+            bytes: vec![0x31, 0x10],
+        });
+
+        assert_eq!(parse_offset("-0x100"), Ok(-0x100));
+        assert_eq!(parse_range("0x100:0x101"), Ok((0x100, 0x101)));
+        assert!(parse_range("0x10000:0x10000").is_err());
+        assert_eq!(offset(&image, -0x100)?.start(), 0x0000);
+        assert_eq!(
+            offset(&image, 0x10)?.segments,
+            vec![Segment {
+                address: 0x0110,
+                bytes: vec![0x31, 0x10],
+            }]
+        );
+        assert!(offset(&image, -0x101).is_err());
+        Ok(())
+    }
+}
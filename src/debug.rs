@@ -0,0 +1,134 @@
+use crate::parse::Line;
+use anyhow::{anyhow, Context, Result};
+use std::io::Write;
+
+/// Source position of the bytes in `start..end`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LineEntry {
+    pub start: u16,
+    pub end: u32,
+    pub file: String,
+    pub line_num: usize,
+    pub column: usize,
+}
+
+/// Maps addresses of an image back to the source, for the tools that only
+/// see the image.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LineTable {
+    pub entries: Vec<LineEntry>,
+}
+
+impl LineTable {
+    /// Builds the table from the placed lines of all sections.
+    pub fn new(lines: &[Line]) -> Self {
+        let mut entries: Vec<LineEntry> = lines
+            .iter()
+            .filter(|l| l.size > 0)
+            .map(|l| LineEntry {
+                start: l.address,
+                end: l.address as u32 + l.size as u32,
+                file: l.file.clone(),
+                line_num: l.line_num,
+                column: l.column,
+            })
+            .collect();
+        entries.sort_by_key(|e| e.start);
+        LineTable { entries }
+    }
+
+    /// Returns the entry whose range contains `address`.
+    pub fn lookup(&self, address: u16) -> Option<&LineEntry> {
+        let index = self.entries.partition_point(|e| e.start <= address);
+        self.entries[..index]
+            .last()
+            .filter(|e| (address as u32) < e.end)
+    }
+}
+
+/// Writes the table as `zktc-lines` followed by one
+/// `start end file:line:column` record per entry.
+pub fn write(table: &LineTable, output: &mut impl Write) -> Result<()> {
+    writeln!(output, "zktc-lines")?;
+    for entry in &table.entries {
+        writeln!(
+            output,
+            "0x{:04x} 0x{:04x} {}:{}:{}",
+            entry.start, entry.end, entry.file, entry.line_num, entry.column
+        )?;
+    }
+    Ok(())
+}
+
+pub fn read(text: &str) -> Result<LineTable> {
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+        Some((_, "zktc-lines")) => {}
+        _ => return Err(anyhow!("not a zktc line table")),
+    }
+
+    let mut table = LineTable::default();
+    for (line_num, line) in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = read_entry(line)
+            .with_context(|| format!("invalid line table : line {}:{}", line_num + 1, line))?;
+        table.entries.push(entry);
+    }
+    table.entries.sort_by_key(|e| e.start);
+    Ok(table)
+}
+
+fn read_entry(line: &str) -> Result<LineEntry> {
+    let fields: Vec<&str> = line.splitn(3, ' ').collect();
+    let [start, end, position] = fields.as_slice() else {
+        return Err(anyhow!("unknown record"));
+    };
+    // the file name may itself contain colons
+    let position: Vec<&str> = position.rsplitn(3, ':').collect();
+    let [column, line_num, file] = position.as_slice() else {
+        return Err(anyhow!("expected file:line:column"));
+    };
+    Ok(LineEntry {
+        start: u16::from_str_radix(start.trim_start_matches("0x"), 16)?,
+        end: u32::from_str_radix(end.trim_start_matches("0x"), 16)?,
+        file: file.to_string(),
+        line_num: line_num.parse()?,
+        column: column.parse()?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::layout::{place, Layout};
+    use crate::parse::{parse, set_file};
+
+    #[test]
+    fn can_write_and_read_line_table() -> Result<()> {
+        let text = "start:\n\tlil x1, 0x00@l\n  lih x1, 0x00@h\n.data\n\t.word 0x1234\n";
+        let (mut sections, mut label_table) = parse(text.to_string())?;
+        set_file(&mut sections, "C:/fw/boot.asm");
+        place(&Layout::new(0x100), &mut sections, &mut label_table)?;
+        let lines: Vec<Line> = sections.iter().flat_map(|s| s.lines.clone()).collect();
+        let table = LineTable::new(&lines);
+
+        let mut output: Vec<u8> = Vec::new();
+        write(&table, &mut output)?;
+        let expect = "\
+zktc-lines
+0x0100 0x0102 C:/fw/boot.asm:2:2
+0x0102 0x0104 C:/fw/boot.asm:3:3
+0x0104 0x0106 C:/fw/boot.asm:5:2
+";
+        assert_eq!(std::str::from_utf8(&output)?, expect);
+        assert_eq!(read(expect)?, table);
+
+        let entry = table.lookup(0x0103).unwrap();
+        assert_eq!((entry.line_num, entry.column), (3, 3));
+        assert_eq!(table.lookup(0x0106), None);
+        assert_eq!(table.lookup(0x00ff), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,488 @@
+use crate::disasm::{disassemble, target};
+use crate::file;
+use crate::format::{self, Endian, Format};
+use crate::image::Image;
+use crate::map::{Symbol, SymbolKind};
+use anyhow::{anyhow, Context, Result};
+use clap_num::maybe_hex;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Options of `zktc-asm diff`.
+#[derive(clap::Args)]
+pub struct DiffArgs {
+    /// old image
+    pub image_a: PathBuf,
+
+    /// new image
+    pub image_b: PathBuf,
+
+    /// image format [default: by the extension of each image file]
+    #[arg(short = 'I', value_enum)]
+    pub image_format: Option<Format>,
+
+    /// symbol map of the old image, written with -m
+    #[arg(long = "map-a")]
+    pub map_a: Option<PathBuf>,
+
+    /// symbol map of the new image, written with -m
+    #[arg(long = "map-b")]
+    pub map_b: Option<PathBuf>,
+
+    /// address of the first byte of mem and binary images
+    #[arg(long = "load-address", default_value_t = 0, value_parser=maybe_hex::<u16>)]
+    pub load_address: u16,
+
+    /// bits per word of verilog, coe and mif images
+    #[arg(long = "word-width", default_value_t = 8, value_parser = format::parse_word_width)]
+    pub word_width: u8,
+
+    /// how bytes are packed into words
+    #[arg(long = "endian", value_enum, default_value_t = Endian::Little)]
+    pub endian: Endian,
+}
+
+/// Bytes compared between the two images, `address` and `size` on each side.
+/// A side is `None` for a label that only exists in the other image.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Region {
+    pub name: Option<String>,
+    pub a: Option<(u16, u32)>,
+    pub b: Option<(u16, u32)>,
+}
+
+/// Largest table for aligning the instructions of a region. Larger regions
+/// are compared by offset.
+const ALIGN_LIMIT: usize = 1 << 22;
+
+/// Ranges of the image outside every label, each with the name of the label
+/// before it.
+fn gaps(image: &Image, labels: &[Symbol]) -> Vec<(Option<String>, u16, u32)> {
+    let mut covered = vec![false; 0x10000];
+    for label in labels {
+        let end = (label.address as usize + label.size as usize).min(0x10000);
+        covered[label.address as usize..end].fill(true);
+    }
+    let mut gaps: Vec<(Option<String>, u16, u32)> = Vec::new();
+    for segment in &image.segments {
+        for address in segment.address as u32..segment.end() {
+            if covered[address as usize] {
+                continue;
+            }
+            match gaps.last_mut() {
+                Some((_, start, size)) if *start as u32 + *size == address => *size += 1,
+                _ => {
+                    let before = labels
+                        .iter()
+                        .filter(|l| (l.address as u32) < address)
+                        .max_by_key(|l| l.address)
+                        .map(|l| l.name.clone());
+                    gaps.push((before, address as u16, 1));
+                }
+            }
+        }
+    }
+    gaps
+}
+
+/// Pairs the labels of both maps by name, and the bytes outside every label
+/// by the label before them. Without both maps the images are compared at
+/// the same addresses.
+pub fn regions(a: &Image, b: &Image, symbols_a: &[Symbol], symbols_b: &[Symbol]) -> Vec<Region> {
+    let labels = |symbols: &[Symbol]| -> Vec<Symbol> {
+        symbols
+            .iter()
+            .filter(|s| s.kind == SymbolKind::Label && s.size > 0)
+            .cloned()
+            .collect()
+    };
+    let (labels_a, labels_b) = (labels(symbols_a), labels(symbols_b));
+    if labels_a.is_empty() || labels_b.is_empty() {
+        let start = a.start().min(b.start());
+        let end = a.end().max(b.end());
+        let range = Some((start, end.saturating_sub(start as u32)));
+        return vec![Region {
+            name: None,
+            a: range,
+            b: range,
+        }];
+    }
+
+    let mut regions: Vec<Region> = labels_a
+        .iter()
+        .map(|label| Region {
+            name: Some(label.name.clone()),
+            a: Some((label.address, label.size as u32)),
+            b: labels_b
+                .iter()
+                .find(|l| l.name == label.name)
+                .map(|l| (l.address, l.size as u32)),
+        })
+        .collect();
+    for label in &labels_b {
+        if !labels_a.iter().any(|l| l.name == label.name) {
+            regions.push(Region {
+                name: Some(label.name.clone()),
+                a: None,
+                b: Some((label.address, label.size as u32)),
+            });
+        }
+    }
+
+    let gap_name = |before: &Option<String>| match before {
+        Some(label) => format!("after {}", label),
+        None => "before the first label".to_string(),
+    };
+    let mut gaps_b = gaps(b, &labels_b);
+    for (before, address, size) in gaps(a, &labels_a) {
+        let other = gaps_b.iter().position(|(other, ..)| *other == before);
+        regions.push(Region {
+            name: Some(gap_name(&before)),
+            a: Some((address, size)),
+            b: other
+                .map(|i| gaps_b.remove(i))
+                .map(|(_, address, size)| (address, size)),
+        });
+    }
+    for (before, address, size) in gaps_b {
+        regions.push(Region {
+            name: Some(gap_name(&before)),
+            a: None,
+            b: Some((address, size)),
+        });
+    }
+    regions.sort_by_key(|r| r.a.or(r.b).map(|(address, _)| address));
+    regions
+}
+
+fn word(image: &Image, address: u32) -> Option<u16> {
+    let low = image.byte(address)?;
+    Some(u16::from_le_bytes([
+        low,
+        image.byte(address + 1).unwrap_or_default(),
+    ]))
+}
+
+/// Names `address` as the label it falls in, e.g. `putc+0x4`.
+fn annotate(address: u16, symbols: &[Symbol]) -> Option<String> {
+    let label = symbols
+        .iter()
+        .filter(|s| s.kind == SymbolKind::Label && s.address <= address)
+        .filter(|s| (address as u32) < s.address as u32 + (s.size as u32).max(1))
+        .max_by_key(|s| s.address)?;
+    Some(match address - label.address {
+        0 => label.name.clone(),
+        offset => format!("{}+0x{:x}", label.name, offset),
+    })
+}
+
+/// One instruction of a side: its word and the label its jump lands in.
+struct Inst {
+    address: u16,
+    word: u16,
+    target: Option<String>,
+}
+
+impl Inst {
+    fn text(&self) -> String {
+        match &self.target {
+            Some(target) => format!("{} <{}>", disassemble(self.word), target),
+            None => disassemble(self.word),
+        }
+    }
+
+    /// Jumps that land at the same label are the same even if their offsets
+    /// differ because the code moved.
+    fn same(&self, other: &Inst) -> bool {
+        match (&self.target, &other.target) {
+            (Some(a), Some(b)) => {
+                // everything but the offset must match
+                let mask = if self.word & 0x001F == 0b10000 {
+                    0x00FF
+                } else {
+                    0x07FF
+                };
+                a == b && self.word & mask == other.word & mask
+            }
+            _ => self.word == other.word,
+        }
+    }
+}
+
+fn inst(image: &Image, address: u32, symbols: &[Symbol]) -> Option<Inst> {
+    let word = word(image, address)?;
+    let address = address as u16;
+    Some(Inst {
+        address,
+        word,
+        target: target(word, address).and_then(|t| annotate(t, symbols)),
+    })
+}
+
+/// Instructions of a region on one side, two bytes apart.
+fn insts(image: &Image, range: Option<(u16, u32)>, symbols: &[Symbol]) -> Vec<Inst> {
+    match range {
+        Some((address, size)) => (0..size)
+            .step_by(2)
+            .filter_map(|offset| inst(image, address as u32 + offset, symbols))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Pairs the instructions that differ, aligned by the longest common
+/// subsequence so that an inserted instruction does not shift the rest.
+/// Unchanged instructions are left out.
+fn align<'a>(a: &'a [Inst], b: &'a [Inst]) -> Vec<(Option<&'a Inst>, Option<&'a Inst>)> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x.same(y)).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x.same(y))
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+
+    if (a.len() + 1) * (b.len() + 1) > ALIGN_LIMIT {
+        return (0..a.len().max(b.len()))
+            .map(|i| (a.get(i), b.get(i)))
+            .filter(|pair| !matches!(pair, (Some(x), Some(y)) if x.same(y)))
+            .collect();
+    }
+
+    let mut rows = Vec::new();
+    let (mut removed, mut added): (Vec<&Inst>, Vec<&Inst>) = (Vec::new(), Vec::new());
+    // removed and added instructions between two common ones are paired up
+    let mut flush = |removed: &mut Vec<&'a Inst>, added: &mut Vec<&'a Inst>| {
+        for i in 0..removed.len().max(added.len()) {
+            rows.push((removed.get(i).copied(), added.get(i).copied()));
+        }
+        removed.clear();
+        added.clear();
+    };
+
+    // common[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+    let width = b.len() + 1;
+    let mut common = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            common[i * width + j] = if a[i].same(&b[j]) {
+                common[(i + 1) * width + j + 1] + 1
+            } else {
+                common[(i + 1) * width + j].max(common[i * width + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i].same(&b[j]) {
+            flush(&mut removed, &mut added);
+            i += 1;
+            j += 1;
+        } else if j == b.len()
+            || (i < a.len() && common[(i + 1) * width + j] >= common[i * width + j + 1])
+        {
+            removed.push(&a[i]);
+            i += 1;
+        } else {
+            added.push(&b[j]);
+            j += 1;
+        }
+    }
+    flush(&mut removed, &mut added);
+    rows
+}
+
+fn side(inst: Option<&Inst>) -> String {
+    match inst {
+        Some(inst) => format!(
+            "{:04x}  {:04x}  {:<30}",
+            inst.address,
+            inst.word,
+            inst.text()
+        ),
+        None => format!("{:42}", ""),
+    }
+}
+
+fn range(range: Option<(u16, u32)>) -> String {
+    match range {
+        Some((address, _)) => format!("0x{:04x}", address),
+        None => "-".to_string(),
+    }
+}
+
+/// Writes the instructions that differ in each region side by side, the old
+/// image on the left. Returns true if the images differ.
+pub fn write(
+    a: &Image,
+    b: &Image,
+    symbols_a: &[Symbol],
+    symbols_b: &[Symbol],
+    regions: &[Region],
+    output: &mut impl Write,
+) -> Result<bool> {
+    let mut differ = false;
+    for region in regions {
+        let insts_a = insts(a, region.a, symbols_a);
+        let insts_b = insts(b, region.b, symbols_b);
+        let rows: Vec<String> = align(&insts_a, &insts_b)
+            .into_iter()
+            .map(|(inst_a, inst_b)| {
+                let marker = match (inst_a, inst_b) {
+                    (Some(_), Some(_)) => '!',
+                    (Some(_), None) => '<',
+                    _ => '>',
+                };
+                format!("{} {} | {}", marker, side(inst_a), side(inst_b))
+            })
+            .collect();
+
+        let moved = matches!((region.a, region.b), (Some(x), Some(y)) if x.0 != y.0);
+        if rows.is_empty() && !moved {
+            continue;
+        }
+        differ = true;
+        let name = region.name.as_deref().unwrap_or("image");
+        let note = match (region.a, region.b) {
+            (None, _) => " (added)",
+            (_, None) => " (removed)",
+            _ if moved => " (moved)",
+            _ => "",
+        };
+        writeln!(
+            output,
+            "@@ {} {} | {}{}",
+            name,
+            range(region.a),
+            range(region.b),
+            note
+        )?;
+        for row in rows {
+            writeln!(output, "{}", row.trim_end())?;
+        }
+    }
+    Ok(differ)
+}
+
+fn read_image(path: &Path, format: Option<Format>, args: &DiffArgs) -> Result<Image> {
+    let format = match format {
+        Some(format) => format,
+        None => path
+            .extension()
+            .and_then(|e| Format::from_extension(&e.to_string_lossy()))
+            .ok_or_else(|| anyhow!("unknown image format, use -I to select it"))?,
+    };
+    let data = file::read_bytes(path)?;
+    format::read(
+        &data,
+        format,
+        args.load_address,
+        args.endian,
+        args.word_width,
+    )
+    .with_context(|| format!("could not read image '{}'", path.display()))
+}
+
+fn read_map(path: &Option<PathBuf>) -> Result<Vec<Symbol>> {
+    match path {
+        Some(path) => crate::map::read(&file::read(path)?)
+            .with_context(|| format!("could not load map '{}'", path.display())),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Compares the images and prints the differences to stdout. Returns true
+/// if the images differ.
+pub fn diff(args: &DiffArgs) -> Result<bool> {
+    file::check_std(
+        [
+            args.image_a.as_path(),
+            args.image_b.as_path(),
+            args.map_a.as_deref().unwrap_or(Path::new("")),
+            args.map_b.as_deref().unwrap_or(Path::new("")),
+        ],
+        [],
+    )?;
+    let a = read_image(&args.image_a, args.image_format, args)?;
+    let b = read_image(&args.image_b, args.image_format, args)?;
+    let symbols_a = read_map(&args.map_a)?;
+    let symbols_b = read_map(&args.map_b)?;
+
+    let regions = regions(&a, &b, &symbols_a, &symbols_b);
+    let mut output: Vec<u8> = Vec::new();
+    writeln!(output, "--- {}", file::source_name(&args.image_a))?;
+    writeln!(output, "+++ {}", file::source_name(&args.image_b))?;
+    let differ = write(&a, &b, &symbols_a, &symbols_b, &regions, &mut output)?;
+    if differ {
+        file::write(Path::new("-"), &output)?;
+    }
+    Ok(differ)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gen::gen;
+    use crate::layout::{place, Layout};
+    use crate::map::symbols;
+    use crate::parse::parse;
+
+    fn build(text: &str) -> Result<(Image, Vec<Symbol>)> {
+        let (mut sections, mut label_table) = parse(text.to_string())?;
+        place(&Layout::new(0x100), &mut sections, &mut label_table)?;
+        let symbols = symbols(&label_table, &sections);
+        Ok((gen(sections, label_table)?, symbols))
+    }
+
+    #[test]
+    fn can_diff_images() -> Result<()> {
+        let (a, symbols_a) = build(
+            "start:\n\tjal ra, putc\n\tjal zero, start\nputc:\n\tlil a0, 0x41@l\n\tjalr zero, ra, 0\n",
+        )?;
+        let (b, symbols_b) = build(
+            "start:\n\tmov a0, zero\n\tjal ra, putc\n\tjal zero, start\nputc:\n\tlil a0, 0x42@l\n\tjalr zero, ra, 0\n",
+        )?;
+
+        let regions = regions(&a, &b, &symbols_a, &symbols_b);
+        let mut output: Vec<u8> = Vec::new();
+        assert!(write(
+            &a,
+            &b,
+            &symbols_a,
+            &symbols_b,
+            &regions,
+            &mut output
+        )?);
+        let expect = "\
+@@ start 0x0100 | 0x0100
+>                                            | 0100  0860  mov x3, x0
+@@ putc 0x0104 | 0x0106 (moved)
+! 0104  4171  lil x3, 0x41@l                 | 0106  4271  lil x3, 0x42@l
+";
+        assert_eq!(std::str::from_utf8(&output)?, expect);
+
+        // without maps the images are compared at the same addresses
+        let regions = super::regions(&a, &a, &[], &[]);
+        assert_eq!(regions[0].a, Some((0x0100, 8)));
+        assert!(!write(&a, &a, &[], &[], &regions, &mut output)?);
+
+        // with maps, bytes outside every label are still compared
+        let (a, symbols_a) = build("\tmov a0, zero\nstart:\n\ttrap\n")?;
+        let (b, symbols_b) = build("\tmov a1, zero\nstart:\n\ttrap\n")?;
+        let regions = super::regions(&a, &b, &symbols_a, &symbols_b);
+        let mut output: Vec<u8> = Vec::new();
+        assert!(write(
+            &a,
+            &b,
+            &symbols_a,
+            &symbols_b,
+            &regions,
+            &mut output
+        )?);
+        assert!(std::str::from_utf8(&output)?
+            .starts_with("@@ before the first label 0x0100 | 0x0100\n! 0100"));
+        Ok(())
+    }
+}
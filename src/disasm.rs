@@ -0,0 +1,144 @@
+const REGISTERS: [&str; 8] = ["x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7"];
+
+/// Sign extends the low `bits` bits of `value`.
+fn signed(value: u16, bits: u32) -> i16 {
+    let shift = 16 - bits;
+    ((value << shift) as i16) >> shift
+}
+
+/// Decodes one instruction word into assembler syntax. Words that are not
+/// instructions are shown as `.word`.
+pub fn disassemble(word: u16) -> String {
+    let opcode = word & 0x001F;
+    let rd = REGISTERS[(word >> 5 & 0x0007) as usize];
+    let rs = REGISTERS[(word >> 8 & 0x0007) as usize];
+    let func = word >> 11 & 0x001F;
+    let imm8 = word >> 8;
+
+    if word == 0xFFFF {
+        return "trap".to_string();
+    }
+    let text = match opcode {
+        0b00000 => {
+            let mnemonic = match func {
+                0b00001 => "mov",
+                0b00010 => "add",
+                0b00011 => "sub",
+                0b00100 => "and",
+                0b00101 => "or",
+                0b00110 => "xor",
+                0b00111 => "sll",
+                0b01000 => "srl",
+                0b01001 => "sra",
+                _ => "",
+            };
+            (!mnemonic.is_empty()).then(|| format!("{} {}, {}", mnemonic, rd, rs))
+        }
+        0b00001 => Some(format!("addi {}, {}, {}", rd, rs, func)),
+        0b00010 => Some(format!("subi {}, {}, {}", rd, rs, func)),
+        0b00011..=0b01110 => {
+            let mnemonic = match opcode {
+                0b00011 => "beq",
+                0b00100 => "bnq",
+                0b00101 => "blt",
+                0b00110 => "bge",
+                0b00111 => "bltu",
+                0b01000 => "bgeu",
+                0b01001 => "jalr",
+                0b01010 => "lh",
+                0b01011 => "lhu",
+                0b01100 => "lw",
+                0b01101 => "sh",
+                _ => "sw",
+            };
+            Some(format!("{} {}, {}, {}", mnemonic, rd, rs, signed(func, 5)))
+        }
+        0b10000 => Some(format!("jal {}, {}", rd, signed(imm8, 8))),
+        0b10001 => Some(format!("lil {}, 0x{:02x}@l", rd, imm8)),
+        0b10010 => Some(format!("lih {}, 0x{:04x}@h", rd, imm8 << 8)),
+        0b11110 if word & 0x0700 == 0 => {
+            let mnemonic = match func {
+                0b00001 => "push",
+                0b00010 => "pop",
+                0b00011 => "rpc",
+                0b00100 => "rsp",
+                0b00101 => "rpsr",
+                0b00110 => "rtlr",
+                0b00111 => "rthr",
+                0b01000 => "rppc",
+                0b01001 => "rppsr",
+                0b01010 => "wsp",
+                0b01011 => "wpsr",
+                0b01100 => "wtlr",
+                0b01101 => "wthr",
+                0b01110 => "wppc",
+                0b01111 => "wppsr",
+                _ => "",
+            };
+            (!mnemonic.is_empty()).then(|| format!("{} {}", mnemonic, rd))
+        }
+        0b11111 if word & 0x07E0 == 0 => match func {
+            0b00001 => Some("rfi".to_string()),
+            0b00010 => Some("rtr".to_string()),
+            0b00011 => Some("wtr".to_string()),
+            _ => None,
+        },
+        _ => None,
+    };
+    text.unwrap_or_else(|| format!(".word 0x{:04x}", word))
+}
+
+/// Returns the address a branch or `jal` at `address` jumps to.
+pub fn target(word: u16, address: u16) -> Option<u16> {
+    let offset = match word & 0x001F {
+        0b00011..=0b01000 => signed(word >> 11, 5),
+        0b10000 => signed(word >> 8, 8),
+        _ => return None,
+    };
+    Some(address.wrapping_add(offset as u16))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gen::gen;
+    use crate::parse::parse;
+
+    #[test]
+    fn can_disassemble_generated_code() -> anyhow::Result<()> {
+        let mut text = String::new();
+        for path in [
+            "test/asm/r_inst_test.asm",
+            "test/asm/i5_inst_test.asm",
+            "test/asm/i8_inst_test.asm",
+            "test/asm/c1_inst_test.asm",
+            "test/asm/c2_inst_test.asm",
+            "test/asm/trap_inst_test.asm",
+        ] {
+            let source = std::fs::read_to_string(path)?;
+            // the fixtures all define start and end
+            text += &source.replace("start:", "").replace("end:", "");
+            text += "\n";
+        }
+        let (sections, label_table) = parse(text)?;
+        let bytes = gen(sections.clone(), label_table.clone())?.to_bytes(0);
+
+        // disassembling and assembling again gives the same bytes
+        let listing: String = bytes
+            .chunks(2)
+            .map(|w| format!("\t{}\n", disassemble(u16::from_le_bytes([w[0], w[1]]))))
+            .collect();
+        let (sections, label_table) = parse(listing.clone())?;
+        assert_eq!(gen(sections, label_table)?.to_bytes(0), bytes);
+        assert!(listing.starts_with("\tmov x0, x0\n\tadd x1, x1\n"));
+        assert!(listing.contains("\tbnq x3, x3, -1\n"));
+        assert!(listing.contains("\tlih x2, 0x0000@h\n"));
+        assert!(listing.ends_with("\twtr\n\ttrap\n"));
+
+        assert_eq!(disassemble(0x000f), ".word 0x000f");
+        assert_eq!(target(0xfb64, 0x0010), Some(0x000f));
+        assert_eq!(target(0x0830, 0x0010), Some(0x0018));
+        assert_eq!(target(0x0001, 0x0010), None);
+        Ok(())
+    }
+}
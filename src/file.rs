@@ -0,0 +1,100 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Returns true if `path` is `-`, which stands for stdin or stdout.
+pub fn is_std(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Reads `path`, or stdin when it is `-`.
+pub fn read(path: &Path) -> Result<String> {
+    let mut text = String::new();
+    if is_std(path) {
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .with_context(|| "could not read stdin".to_string())?;
+    } else {
+        text = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read file '{}'", path.display()))?;
+    }
+    Ok(text)
+}
+
+/// Reads the bytes of `path`, or of stdin when it is `-`.
+pub fn read_bytes(path: &Path) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    if is_std(path) {
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .with_context(|| "could not read stdin".to_string())?;
+    } else {
+        bytes = std::fs::read(path)
+            .with_context(|| format!("could not read file '{}'", path.display()))?;
+    }
+    Ok(bytes)
+}
+
+/// Writes `bytes` to `path`, or to stdout when it is `-`.
+pub fn write(path: &Path, bytes: &[u8]) -> Result<()> {
+    if is_std(path) {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(bytes)?;
+        stdout.flush()?;
+        return Ok(());
+    }
+    std::fs::write(path, bytes)
+        .with_context(|| format!("could not create file '{}'", path.display()))
+}
+
+/// Returns the module name of an input file, its file name without the
+/// extension, or `stdin` for `-`.
+pub fn module_name(path: &Path) -> String {
+    if is_std(path) {
+        return "stdin".to_string();
+    }
+    path.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Returns the name of an input file as shown in line tables, `<stdin>`
+/// for `-`.
+pub fn source_name(path: &Path) -> String {
+    if is_std(path) {
+        return "<stdin>".to_string();
+    }
+    path.display().to_string()
+}
+
+/// Checks that stdin is read at most once and that at most one output goes to
+/// stdout.
+pub fn check_std<'a>(
+    inputs: impl IntoIterator<Item = &'a Path>,
+    outputs: impl IntoIterator<Item = &'a Path>,
+) -> Result<()> {
+    if inputs.into_iter().filter(|p| is_std(p)).count() > 1 {
+        return Err(anyhow!("stdin can be read only once"));
+    }
+    if outputs.into_iter().filter(|p| is_std(p)).count() > 1 {
+        return Err(anyhow!("only one output can be written to stdout"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn can_check_std() {
+        let stdin = Path::new("-");
+        let file = Path::new("a.asm");
+        assert_eq!(module_name(stdin), "stdin");
+        assert_eq!(module_name(Path::new("dir/boot.asm")), "boot");
+        assert!(check_std([stdin, file], [file]).is_ok());
+        assert!(check_std([stdin, stdin], []).is_err());
+        assert!(check_std([file], [stdin, stdin]).is_err());
+    }
+}
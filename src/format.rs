@@ -0,0 +1,1473 @@
+use crate::file;
+use crate::image::{Image, Segment};
+use crate::layout::over_limit;
+use crate::parse::{Binding, Label, Line};
+use anyhow::{anyhow, Result};
+use clap_num::maybe_hex;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::ValueEnum, Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    /// text file with one byte per line
+    Mem,
+    /// raw bytes
+    Binary,
+    /// Intel HEX
+    Ihex,
+    /// Motorola S-record
+    Srec,
+    /// Verilog $readmemh with @address markers
+    Verilog,
+    /// Xilinx memory initialization file
+    Coe,
+    /// Intel (Altera) memory initialization file
+    Mif,
+}
+
+#[derive(clap::ValueEnum, Debug, PartialEq, Clone, Copy)]
+pub enum Endian {
+    /// the byte at the lower address is the low byte of the word
+    Little,
+    /// the byte at the lower address is the high byte of the word
+    Big,
+}
+
+#[derive(clap::ValueEnum, Debug, PartialEq, Clone, Copy)]
+pub enum Radix {
+    Hex,
+    Bin,
+    Dec,
+}
+
+impl Radix {
+    fn format(&self, word: u32, width: u8) -> String {
+        match self {
+            Radix::Hex => format!("{:0w$x}", word, w = width as usize / 4),
+            Radix::Bin => format!("{:0w$b}", word, w = width as usize),
+            Radix::Dec => format!("{}", word),
+        }
+    }
+}
+
+impl Format {
+    /// Guesses the format of a file from its extension.
+    pub fn from_extension(extension: &str) -> Option<Format> {
+        match extension {
+            "mem" => Some(Format::Mem),
+            "bin" => Some(Format::Binary),
+            "hex" | "ihex" => Some(Format::Ihex),
+            "srec" | "s19" | "mot" => Some(Format::Srec),
+            "vmem" => Some(Format::Verilog),
+            "coe" => Some(Format::Coe),
+            "mif" => Some(Format::Mif),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Mem => "mem",
+            Format::Binary => "bin",
+            Format::Ihex => "hex",
+            Format::Srec => "srec",
+            Format::Verilog => "vmem",
+            Format::Coe => "coe",
+            Format::Mif => "mif",
+        }
+    }
+}
+
+/// Output format and image shaping, used by both binaries and `convert`.
+#[derive(clap::Args, Clone)]
+pub struct FormatArgs {
+    /// output format
+    #[arg(short = 'O', value_enum, default_value_t = Format::Mem)]
+    pub format: Format,
+
+    /// fill gaps between sections, and the padding of --image-size, with this byte [mem default: 0, binary default: error]
+    #[arg(long = "gap-fill", visible_alias = "fill", value_parser=maybe_hex::<u8>)]
+    pub gap_fill: Option<u8>,
+
+    /// address the output starts at, for mem and binary [default: start of the image]
+    #[arg(long = "start-address", value_parser=maybe_hex::<u16>)]
+    pub start_address: Option<u16>,
+
+    /// entry symbol or address written to the srec start address record [default: start, or the start of the image]
+    #[arg(long = "entry")]
+    pub entry: Option<String>,
+
+    /// bits per word of verilog, coe and mif output
+    #[arg(long = "word-width", default_value_t = 8, value_parser = parse_word_width)]
+    pub word_width: u8,
+
+    /// how bytes are packed into words
+    #[arg(long = "endian", value_enum, default_value_t = Endian::Little)]
+    pub endian: Endian,
+
+    /// add the source of each word as a comment to verilog output
+    #[arg(long = "comments")]
+    pub comments: bool,
+
+    /// number of words in coe and mif output [default: enough for the image]
+    #[arg(long = "depth")]
+    pub depth: Option<u32>,
+
+    /// radix of the data in coe and mif output
+    #[arg(long = "radix", value_enum, default_value_t = Radix::Hex)]
+    pub radix: Radix,
+
+    /// pad the image to exactly this many bytes from the start address
+    #[arg(long = "image-size", value_parser=maybe_hex::<u32>)]
+    pub image_size: Option<u32>,
+
+    /// fail if the image is larger than this many bytes from the start address
+    #[arg(long = "max-size", value_parser=maybe_hex::<u32>)]
+    pub max_size: Option<u32>,
+
+    /// split the image into this many interleaved byte lanes, each written to
+    /// its own file with the lane number added to the name
+    #[arg(long = "lanes", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..=8))]
+    pub lanes: u16,
+}
+
+impl FormatArgs {
+    /// Returns the options of `format` with everything else at its default.
+    pub fn new(format: Format) -> Self {
+        FormatArgs {
+            format,
+            gap_fill: None,
+            start_address: None,
+            entry: None,
+            word_width: 8,
+            endian: Endian::Little,
+            comments: false,
+            depth: None,
+            radix: Radix::Hex,
+            image_size: None,
+            max_size: None,
+            lanes: 1,
+        }
+    }
+}
+
+/// What an image file records besides its bytes, so that a file that was
+/// read can be written back the way it was.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FileInfo {
+    /// S0 header of S-records, or the leading comment of mif files
+    pub name: Option<String>,
+    /// S9 start address of S-records
+    pub entry: Option<u16>,
+    /// bits per word of verilog, coe and mif files
+    pub word_width: Option<u32>,
+    /// words in coe and mif files
+    pub depth: Option<u32>,
+    /// data radix of coe and mif files, `None` for radixes that cannot be written
+    pub radix: Option<Radix>,
+}
+
+impl FileInfo {
+    /// Sets the options of `args` recorded in the file.
+    pub fn apply(&self, args: &mut FormatArgs) -> Result<()> {
+        if let Some(entry) = self.entry {
+            args.entry = Some(format!("0x{:04x}", entry));
+        }
+        if let Some(width) = self.word_width {
+            args.word_width = parse_word_width(&width.to_string()).map_err(|e| anyhow!(e))?;
+        }
+        if matches!(args.format, Format::Coe | Format::Mif) {
+            args.radix = self
+                .radix
+                .ok_or_else(|| anyhow!("the data radix of the file cannot be written"))?;
+            args.depth = self.depth;
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_word_width(s: &str) -> Result<u8, String> {
+    match s {
+        "8" => Ok(8),
+        "16" => Ok(16),
+        _ => Err(format!("word width must be 8 or 16, got '{}'", s)),
+    }
+}
+
+/// Writes the image to `path`, or with `--lanes` each lane to a file named
+/// by [`lane_path`].
+pub fn write_file(
+    image: &Image,
+    label_table: &[Label],
+    lines: &[Line],
+    name: &str,
+    args: &FormatArgs,
+    path: &Path,
+) -> Result<()> {
+    if args.lanes == 1 {
+        let mut output: Vec<u8> = Vec::new();
+        write(image, label_table, lines, name, args, &mut output)?;
+        return file::write(path, &output);
+    }
+
+    if file::is_std(path) {
+        return Err(anyhow!("--lanes cannot write to stdout"));
+    }
+    let count = args.lanes;
+    if args.image_size.is_some_and(|size| size % count as u32 != 0) {
+        return Err(anyhow!("--image-size must be a multiple of --lanes"));
+    }
+    // each lane holds every count-th byte, so its addresses and sizes shrink.
+    // All lanes start at the same row, even where a lane has no byte there.
+    let start = args.start_address.unwrap_or(image.start());
+    let lane_args = FormatArgs {
+        start_address: Some(start / count),
+        depth: args.depth.map(|d| d.div_ceil(count as u32)),
+        image_size: args.image_size.map(|size| size / count as u32),
+        ..args.clone()
+    };
+    let label_table: Vec<Label> = label_table
+        .iter()
+        .map(|l| Label {
+            address: l.address / count,
+            ..l.clone()
+        })
+        .collect();
+    for index in 0..count {
+        let lines: Vec<Line> = lines
+            .iter()
+            .filter(|l| l.address % count == index)
+            .map(|l| Line {
+                address: l.address / count,
+                ..l.clone()
+            })
+            .collect();
+        let mut output: Vec<u8> = Vec::new();
+        write(
+            &image.lane(index, count),
+            &label_table,
+            &lines,
+            name,
+            &lane_args,
+            &mut output,
+        )?;
+        let path = lane_path(path, index);
+        file::write(&path, &output)?;
+    }
+    Ok(())
+}
+
+/// Adds the lane number to a file name, `rom.mem` becomes `rom_0.mem`.
+pub fn lane_path(path: &Path, index: u16) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}_{}.{}", stem, index, extension.to_string_lossy()),
+        None => format!("{}_{}", stem, index),
+    };
+    path.with_file_name(name)
+}
+
+/// Checks the image against `--max-size` and `--image-size`, naming the labels
+/// that do not fit.
+pub fn check_size(image: &Image, label_table: &[Label], args: &FormatArgs) -> Result<()> {
+    if image.segments.is_empty() {
+        return Ok(());
+    }
+    let start = args.start_address.unwrap_or(image.start()) as u32;
+    let size = image.end().saturating_sub(start);
+    for limit in [args.max_size, args.image_size].into_iter().flatten() {
+        if size > limit {
+            return Err(over_limit(
+                "image",
+                start,
+                size,
+                limit,
+                label_table.iter().collect(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Pads the image to `size` bytes from `start`, filling everything not
+/// covered by a segment with `fill`.
+pub fn pad(image: &Image, start: u16, size: u32, fill: u8) -> Result<Image> {
+    if start as u32 + size > 0x10000 {
+        return Err(anyhow!(
+            "image of {} bytes at 0x{:04x} does not fit in memory",
+            size,
+            start
+        ));
+    }
+    let mut padded = Image::default();
+    padded.push(Segment {
+        address: start,
+        bytes: (start as u32..start as u32 + size)
+            .map(|a| image.byte(a).unwrap_or(fill))
+            .collect(),
+    });
+    Ok(padded)
+}
+
+/// Writes the image in the selected format. `name` is the module name of
+/// formats with a header, `label_table` is used to find the entry point and
+/// `lines` to comment the output.
+pub fn write(
+    image: &Image,
+    label_table: &[Label],
+    lines: &[Line],
+    name: &str,
+    args: &FormatArgs,
+    output: &mut impl Write,
+) -> Result<()> {
+    let padded;
+    let image = match args.image_size {
+        Some(size) => {
+            let start = args.start_address.unwrap_or(image.start());
+            padded = pad(image, start, size, args.gap_fill.unwrap_or(0))?;
+            &padded
+        }
+        None => image,
+    };
+    match args.format {
+        Format::Mem => {
+            let bytes = flatten(image, args.start_address, Some(args.gap_fill.unwrap_or(0)))?;
+            write_mem(&bytes, output)
+        }
+        Format::Binary => {
+            let bytes = flatten(image, args.start_address, args.gap_fill)?;
+            output.write_all(&bytes)?;
+            Ok(())
+        }
+        Format::Ihex => write_ihex(image, output),
+        Format::Srec => {
+            let entry = entry(image, label_table, args.entry.as_deref())?;
+            write_srec(image, name, entry, output)
+        }
+        Format::Verilog => write_verilog(image, lines, args, output),
+        Format::Coe => {
+            let words = words(image, args)?;
+            write_coe(&words, args, output)
+        }
+        Format::Mif => {
+            let words = words(image, args)?;
+            write_mif(&words, name, args, output)
+        }
+    }
+}
+
+/// Packs the image from `--start-address` into words of `--word-width` bits,
+/// padded with `--gap-fill` up to `--depth` words.
+pub fn words(image: &Image, args: &FormatArgs) -> Result<Vec<u32>> {
+    let fill = args.gap_fill.unwrap_or(0);
+    let size = args.word_width as usize / 8;
+    let bytes = flatten(image, args.start_address, Some(fill))?;
+
+    let mut words: Vec<u32> = bytes
+        .chunks(size)
+        .map(|chunk| {
+            let mut chunk = chunk.to_vec();
+            chunk.resize(size, fill);
+            if args.endian == Endian::Little {
+                chunk.reverse();
+            }
+            chunk.iter().fold(0, |word, b| word << 8 | *b as u32)
+        })
+        .collect();
+
+    if let Some(depth) = args.depth {
+        if words.len() > depth as usize {
+            return Err(anyhow!(
+                "image needs {} words, more than the depth {}",
+                words.len(),
+                depth
+            ));
+        }
+        let fill = (0..size).fold(0, |word, _| word << 8 | fill as u32);
+        words.resize(depth as usize, fill);
+    }
+    Ok(words)
+}
+
+/// Writes a Xilinx COE file with one word per line.
+pub fn write_coe(words: &[u32], args: &FormatArgs, output: &mut impl Write) -> Result<()> {
+    let radix = match args.radix {
+        Radix::Hex => 16,
+        Radix::Bin => 2,
+        Radix::Dec => 10,
+    };
+    writeln!(output, "memory_initialization_radix={};", radix)?;
+    writeln!(output, "memory_initialization_vector=")?;
+    if words.is_empty() {
+        writeln!(output, ";")?;
+    }
+    for (i, word) in words.iter().enumerate() {
+        let end = if i + 1 == words.len() { ';' } else { ',' };
+        writeln!(
+            output,
+            "{}{}",
+            args.radix.format(*word, args.word_width),
+            end
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes an Intel MIF file. Runs of the same word are written as one
+/// `[first..last]` range.
+pub fn write_mif(
+    words: &[u32],
+    name: &str,
+    args: &FormatArgs,
+    output: &mut impl Write,
+) -> Result<()> {
+    let radix = match args.radix {
+        Radix::Hex => "HEX",
+        Radix::Bin => "BIN",
+        Radix::Dec => "UNS",
+    };
+    writeln!(output, "-- {}", name)?;
+    writeln!(output, "WIDTH={};", args.word_width)?;
+    writeln!(output, "DEPTH={};", words.len())?;
+    writeln!(output)?;
+    writeln!(output, "ADDRESS_RADIX=HEX;")?;
+    writeln!(output, "DATA_RADIX={};", radix)?;
+    writeln!(output)?;
+    writeln!(output, "CONTENT BEGIN")?;
+    let mut first = 0;
+    while first < words.len() {
+        let word = words[first];
+        let last = first + words[first..].iter().take_while(|w| **w == word).count() - 1;
+        let value = args.radix.format(word, args.word_width);
+        if first == last {
+            writeln!(output, "\t{:x} : {};", first, value)?;
+        } else {
+            writeln!(output, "\t[{:x}..{:x}] : {};", first, last, value)?;
+        }
+        first = last + 1;
+    }
+    writeln!(output, "END;")?;
+    Ok(())
+}
+
+/// Writes words for `$readmemh` with an `@address` marker, in words, at the
+/// start of every section and after every gap. Bytes missing from a partly
+/// filled word are taken from `--gap-fill`.
+pub fn write_verilog(
+    image: &Image,
+    lines: &[Line],
+    args: &FormatArgs,
+    output: &mut impl Write,
+) -> Result<()> {
+    let size = args.word_width as u32 / 8;
+    let fill = args.gap_fill.unwrap_or(0);
+    let mut next: Option<u32> = None;
+
+    for segment in &image.segments {
+        let first = segment.address as u32 / size;
+        let last = (segment.end() - 1) / size;
+        for word in first..=last {
+            // already written with the end of the previous segment
+            if next.is_some_and(|next| word < next) {
+                continue;
+            }
+            if word == first || next != Some(word) {
+                writeln!(output, "@{:x}", word)?;
+            }
+            let address = word * size;
+            let mut bytes: Vec<u8> = (address..address + size)
+                .map(|a| image.byte(a).unwrap_or(fill))
+                .collect();
+            if args.endian == Endian::Little {
+                bytes.reverse();
+            }
+            let value: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            write!(output, "{}", value)?;
+
+            if args.comments {
+                let texts: Vec<&str> = lines
+                    .iter()
+                    .filter(|l| (address..address + size).contains(&(l.address as u32)))
+                    .map(|l| l.text.as_str())
+                    .collect();
+                if !texts.is_empty() {
+                    write!(output, " // {}", texts.join("; "))?;
+                }
+            }
+            writeln!(output)?;
+            next = Some(word + 1);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the address of the entry symbol, which defaults to `start`.
+pub fn entry(image: &Image, label_table: &[Label], symbol: Option<&str>) -> Result<u16> {
+    let name = symbol.unwrap_or("start");
+    match label_table
+        .iter()
+        .find(|l| l.name == name && l.binding != Binding::Extern)
+    {
+        Some(label) => Ok(label.address),
+        None if symbol.is_none() => Ok(image.start()),
+        None => match crate::parse::number(name) {
+            Ok(("", address)) if address <= 0xFFFF => Ok(address as u16),
+            _ => Err(anyhow!("entry symbol {} is not defined", name)),
+        },
+    }
+}
+
+/// Writes Intel HEX data records for each segment, so gaps are left out
+/// instead of being padded.
+pub fn write_ihex(image: &Image, output: &mut impl Write) -> Result<()> {
+    let mut upper: u32 = 0;
+    for segment in &image.segments {
+        for (i, chunk) in segment.bytes.chunks(16).enumerate() {
+            let address = segment.address as u32 + i as u32 * 16;
+            // extended linear address for data above 64 KiB
+            if address >> 16 != upper {
+                upper = address >> 16;
+                write_ihex_record(output, 0x04, 0, &(upper as u16).to_be_bytes())?;
+            }
+            write_ihex_record(output, 0x00, address as u16, chunk)?;
+        }
+    }
+    write_ihex_record(output, 0x01, 0, &[])
+}
+
+fn write_ihex_record(
+    output: &mut impl Write,
+    record_type: u8,
+    address: u16,
+    data: &[u8],
+) -> Result<()> {
+    let mut record = vec![data.len() as u8];
+    record.extend(address.to_be_bytes());
+    record.push(record_type);
+    record.extend(data);
+    let sum = record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    record.push(sum.wrapping_neg());
+
+    let record: String = record.iter().map(|b| format!("{:02X}", b)).collect();
+    writeln!(output, ":{}", record)?;
+    Ok(())
+}
+
+/// Writes the `mem` text format, one byte per line.
+pub fn write_mem(bytes: &[u8], output: &mut impl Write) -> Result<()> {
+    for byte in bytes {
+        writeln!(output, "{:02x}", byte)?;
+    }
+    Ok(())
+}
+
+/// Flattens the image into bytes from `start_address`. Gaps are filled with
+/// `gap_fill`, or are an error when it is `None`.
+pub fn flatten(image: &Image, start_address: Option<u16>, gap_fill: Option<u8>) -> Result<Vec<u8>> {
+    let start = start_address.unwrap_or(image.start());
+    if image.segments.is_empty() {
+        return Ok(Vec::new());
+    }
+    if image.start() < start {
+        return Err(anyhow!(
+            "image starts at 0x{:04x}, before the start address 0x{:04x}",
+            image.start(),
+            start
+        ));
+    }
+
+    let mut gaps = image.gaps();
+    if image.start() > start {
+        gaps.insert(0, (start, image.start() as u32));
+    }
+    let fill = match (gap_fill, gaps.first()) {
+        (Some(fill), _) => fill,
+        (None, None) => 0,
+        (None, Some((from, to))) => {
+            return Err(anyhow!(
+                "gap at 0x{:04x}-0x{:04x} in the image, use --gap-fill to fill it",
+                from,
+                to - 1
+            ));
+        }
+    };
+
+    let mut bytes = vec![fill; (image.start() - start) as usize];
+    bytes.extend(image.to_bytes(fill));
+    Ok(bytes)
+}
+
+/// Writes an S0 header with `name`, S1 data records, an S5 record count and
+/// an S9 record with the `entry` address.
+pub fn write_srec(image: &Image, name: &str, entry: u16, output: &mut impl Write) -> Result<()> {
+    write_srec_record(output, '0', 0, name.as_bytes())?;
+    let mut count: u32 = 0;
+    for segment in &image.segments {
+        for (i, chunk) in segment.bytes.chunks(16).enumerate() {
+            let address = segment.address + i as u16 * 16;
+            write_srec_record(output, '1', address, chunk)?;
+            count += 1;
+        }
+    }
+    if count <= 0xFFFF {
+        write_srec_record(output, '5', count as u16, &[])?;
+    }
+    write_srec_record(output, '9', entry, &[])
+}
+
+fn write_srec_record(
+    output: &mut impl Write,
+    record_type: char,
+    address: u16,
+    data: &[u8],
+) -> Result<()> {
+    let mut record = vec![data.len() as u8 + 3];
+    record.extend(address.to_be_bytes());
+    record.extend(data);
+    let sum = record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    record.push(!sum);
+
+    let record: String = record.iter().map(|b| format!("{:02X}", b)).collect();
+    writeln!(output, "S{}{}", record_type, record)?;
+    Ok(())
+}
+
+/// Reads an image written in `format`. Formats without addresses (mem,
+/// binary) are loaded at `load_address`, words of word-based formats are
+/// unpacked with `endian`, and `word_width` is used where the file does not
+/// tell it.
+pub fn read(
+    data: &[u8],
+    format: Format,
+    load_address: u16,
+    endian: Endian,
+    word_width: u8,
+) -> Result<Image> {
+    read_with_info(data, format, load_address, endian, word_width).map(|(image, _)| image)
+}
+
+/// Reads an image like `read`, and also returns what the file records about
+/// how it was written.
+pub fn read_with_info(
+    data: &[u8],
+    format: Format,
+    load_address: u16,
+    endian: Endian,
+    word_width: u8,
+) -> Result<(Image, FileInfo)> {
+    let mut image = Image::default();
+    let mut info = FileInfo::default();
+    if format == Format::Binary {
+        if load_address as usize + data.len() > 0x10000 {
+            return Err(anyhow!("binary does not fit in memory"));
+        }
+        image.write(load_address, data);
+        return Ok((image, info));
+    }
+
+    let text = std::str::from_utf8(data).map_err(|_| anyhow!("not a text file"))?;
+    match format {
+        Format::Mem => {
+            let mut address = load_address as u32;
+            for (line_num, line) in text.lines().enumerate() {
+                let line = line.split("//").next().unwrap_or_default().trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let byte = u8::from_str_radix(line, 16)
+                    .map_err(|_| anyhow!("invalid byte : line {}:{}", line_num + 1, line))?;
+                put_bytes(&mut image, address, &[byte])?;
+                address += 1;
+            }
+        }
+        Format::Ihex => read_ihex(&mut image, text)?,
+        Format::Srec => read_srec(&mut image, &mut info, text)?,
+        Format::Verilog => read_verilog(&mut image, &mut info, text, endian)?,
+        Format::Coe => read_coe(&mut image, &mut info, text, endian, word_width)?,
+        Format::Mif => read_mif(&mut image, &mut info, text, endian)?,
+        Format::Binary => unreachable!(),
+    }
+    Ok((image, info))
+}
+
+fn put_bytes(image: &mut Image, address: u32, bytes: &[u8]) -> Result<()> {
+    if address
+        .checked_add(bytes.len() as u32)
+        .is_none_or(|end| end > 0x10000)
+    {
+        return Err(anyhow!("data at 0x{:x} does not fit in memory", address));
+    }
+    image.write(address as u16, bytes);
+    Ok(())
+}
+
+/// Splits `word` of `size` bytes into bytes in address order.
+fn word_bytes(word: u32, size: usize, endian: Endian) -> Vec<u8> {
+    let mut bytes: Vec<u8> = (0..size).map(|i| (word >> (8 * i)) as u8).collect();
+    if endian == Endian::Big {
+        bytes.reverse();
+    }
+    bytes
+}
+
+/// Decodes the hex digits of an Intel HEX or S-record record.
+fn record_bytes(hex: &str, line_num: usize) -> Result<Vec<u8>> {
+    if !hex.is_ascii() {
+        return Err(anyhow!("invalid digits : line {}", line_num + 1));
+    }
+    if !hex.len().is_multiple_of(2) {
+        return Err(anyhow!("odd number of digits : line {}", line_num + 1));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| anyhow!("invalid digits : line {}", line_num + 1))
+        })
+        .collect()
+}
+
+fn read_ihex(image: &mut Image, text: &str) -> Result<()> {
+    let mut upper: u32 = 0;
+    for (line_num, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let hex = line
+            .strip_prefix(':')
+            .ok_or_else(|| anyhow!("expected ':' : line {}", line_num + 1))?;
+        let record = record_bytes(hex, line_num)?;
+        if record.len() < 5 || record.len() != record[0] as usize + 5 {
+            return Err(anyhow!("invalid record length : line {}", line_num + 1));
+        }
+        if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(anyhow!("checksum mismatch : line {}", line_num + 1));
+        }
+        let address = u16::from_be_bytes([record[1], record[2]]) as u32;
+        let data = &record[4..record.len() - 1];
+        match record[3] {
+            0x00 => {
+                let address = upper + address;
+                if address > 0xFFFF {
+                    return Err(anyhow!("address 0x{:x} is above 64 KiB", address));
+                }
+                put_bytes(image, address, data)?;
+            }
+            0x01 => break,
+            0x02 if data.len() == 2 => upper = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            0x04 if data.len() == 2 => {
+                upper = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16
+            }
+            0x03 | 0x05 => {}
+            _ => return Err(anyhow!("unknown record : line {}", line_num + 1)),
+        }
+    }
+    Ok(())
+}
+
+fn read_srec(image: &mut Image, info: &mut FileInfo, text: &str) -> Result<()> {
+    for (line_num, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !line.is_ascii() {
+            return Err(anyhow!("invalid digits : line {}", line_num + 1));
+        }
+        let (record_type, hex) = match line.strip_prefix('S') {
+            Some(rest) if !rest.is_empty() => rest.split_at(1),
+            _ => return Err(anyhow!("expected 'S' : line {}", line_num + 1)),
+        };
+        let record = record_bytes(hex, line_num)?;
+        if record.is_empty() || record.len() != record[0] as usize + 1 {
+            return Err(anyhow!("invalid record length : line {}", line_num + 1));
+        }
+        if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xFF {
+            return Err(anyhow!("checksum mismatch : line {}", line_num + 1));
+        }
+        let address_size = match record_type {
+            "0" | "1" | "5" | "9" => 2,
+            "2" | "6" | "8" => 3,
+            "3" | "7" => 4,
+            _ => return Err(anyhow!("unknown record : line {}", line_num + 1)),
+        };
+        if record.len() < address_size + 2 {
+            return Err(anyhow!("invalid record length : line {}", line_num + 1));
+        }
+        let address = record[1..1 + address_size]
+            .iter()
+            .fold(0u32, |address, b| address << 8 | *b as u32);
+        let data = &record[1 + address_size..record.len() - 1];
+        match record_type {
+            "0" => {
+                info.name = Some(String::from_utf8_lossy(data).to_string());
+                continue;
+            }
+            "7" | "8" | "9" => {
+                info.entry = u16::try_from(address).ok();
+                continue;
+            }
+            "5" | "6" => continue,
+            _ => {}
+        }
+        if address > 0xFFFF {
+            return Err(anyhow!("address 0x{:x} is above 64 KiB", address));
+        }
+        put_bytes(image, address, data)?;
+    }
+    Ok(())
+}
+
+fn read_verilog(image: &mut Image, info: &mut FileInfo, text: &str, endian: Endian) -> Result<()> {
+    let mut word: u32 = 0;
+    for (line_num, line) in text.lines().enumerate() {
+        let line = line.split("//").next().unwrap_or_default();
+        for token in line.split_whitespace() {
+            if let Some(address) = token.strip_prefix('@') {
+                word = u32::from_str_radix(address, 16)
+                    .map_err(|_| anyhow!("invalid address : line {}:{}", line_num + 1, line))?;
+                continue;
+            }
+            let size = token.len().div_ceil(2);
+            let value = u32::from_str_radix(token, 16)
+                .map_err(|_| anyhow!("invalid word : line {}:{}", line_num + 1, line))?;
+            info.word_width.get_or_insert(size as u32 * 8);
+            let address = word.checked_mul(size as u32).ok_or_else(|| {
+                anyhow!("address is above 64 KiB : line {}:{}", line_num + 1, line)
+            })?;
+            put_bytes(image, address, &word_bytes(value, size, endian))?;
+            word += 1;
+        }
+    }
+    Ok(())
+}
+
+fn read_coe(
+    image: &mut Image,
+    info: &mut FileInfo,
+    text: &str,
+    endian: Endian,
+    word_width: u8,
+) -> Result<()> {
+    // comments start with ';', statements end with ';'
+    let text: String = text
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.starts_with(';'))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut radix = 16;
+    let mut words: Vec<&str> = Vec::new();
+    for statement in text.split(';') {
+        let (key, value) = match statement.split_once('=') {
+            Some((key, value)) => (key.trim().to_lowercase(), value),
+            None => continue,
+        };
+        match key.as_str() {
+            "memory_initialization_radix" => {
+                radix = match value.trim() {
+                    "2" => 2,
+                    "10" => 10,
+                    "16" => 16,
+                    _ => return Err(anyhow!("invalid radix {}", value.trim())),
+                };
+            }
+            "memory_initialization_vector" => {
+                words = value
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|w| !w.is_empty())
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+
+    info.depth = Some(words.len() as u32);
+    info.radix = Some(match radix {
+        16 => Radix::Hex,
+        2 => Radix::Bin,
+        _ => Radix::Dec,
+    });
+    let mut address: u32 = 0;
+    for word in words {
+        let size = match radix {
+            16 => word.len().div_ceil(2),
+            2 => word.len().div_ceil(8),
+            _ => word_width as usize / 8,
+        };
+        if radix != 10 {
+            info.word_width.get_or_insert(size as u32 * 8);
+        }
+        let value =
+            u32::from_str_radix(word, radix).map_err(|_| anyhow!("invalid word {}", word))?;
+        put_bytes(image, address, &word_bytes(value, size, endian))?;
+        address += size as u32;
+    }
+    Ok(())
+}
+
+fn read_mif(image: &mut Image, info: &mut FileInfo, text: &str, endian: Endian) -> Result<()> {
+    // `write_mif` starts the file with the module name as a comment
+    info.name = text
+        .lines()
+        .next()
+        .and_then(|l| l.strip_prefix("-- "))
+        .map(|name| name.trim().to_string());
+    let text: String = text
+        .lines()
+        .map(|l| l.split("--").next().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let (header, content) = text
+        .split_once("CONTENT BEGIN")
+        .ok_or_else(|| anyhow!("CONTENT BEGIN is missing"))?;
+
+    let mut width: u32 = 8;
+    let mut address_radix = 16;
+    let mut data_radix = 16;
+    info.radix = Some(Radix::Hex);
+    for statement in header.split(';') {
+        let (key, value) = match statement.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        let radix = match value {
+            "HEX" => 16,
+            "BIN" => 2,
+            "OCT" => 8,
+            "DEC" | "UNS" => 10,
+            _ => 0,
+        };
+        match key {
+            "WIDTH" => width = value.parse().map_err(|_| anyhow!("invalid width"))?,
+            "DEPTH" => info.depth = Some(value.parse().map_err(|_| anyhow!("invalid depth"))?),
+            "ADDRESS_RADIX" => address_radix = radix,
+            "DATA_RADIX" => {
+                data_radix = radix;
+                info.radix = match radix {
+                    16 => Some(Radix::Hex),
+                    2 => Some(Radix::Bin),
+                    10 => Some(Radix::Dec),
+                    _ => None,
+                };
+            }
+            _ => {}
+        }
+    }
+    if width == 0 || !width.is_multiple_of(8) || width > 32 || address_radix == 0 || data_radix == 0
+    {
+        return Err(anyhow!("unsupported width or radix"));
+    }
+    let size = width / 8;
+    info.word_width = Some(width);
+
+    for statement in content.split(';') {
+        let statement = statement.trim();
+        if statement.is_empty() || statement == "END" {
+            continue;
+        }
+        let (addresses, values) = statement
+            .split_once(':')
+            .ok_or_else(|| anyhow!("invalid content {}", statement))?;
+        let parse = |s: &str, radix| {
+            u32::from_str_radix(s.trim(), radix).map_err(|_| anyhow!("invalid number {}", s))
+        };
+        let values: Vec<u32> = values
+            .split_whitespace()
+            .map(|v| parse(v, data_radix))
+            .collect::<Result<_>>()?;
+        let addresses = addresses.trim();
+        let (first, last) = match addresses
+            .strip_prefix('[')
+            .and_then(|a| a.strip_suffix(']'))
+        {
+            Some(range) => {
+                let (first, last) = range
+                    .split_once("..")
+                    .ok_or_else(|| anyhow!("invalid range {}", addresses))?;
+                (parse(first, address_radix)?, parse(last, address_radix)?)
+            }
+            None => {
+                let first = parse(addresses, address_radix)?;
+                let last = first
+                    .checked_add(values.len().max(1) as u32 - 1)
+                    .ok_or_else(|| anyhow!("address {} is above 64 KiB", addresses))?;
+                (first, last)
+            }
+        };
+        if values.is_empty() {
+            return Err(anyhow!("missing value at {}", addresses));
+        }
+        for (i, word) in (first..=last).enumerate() {
+            let value = values[i % values.len()];
+            let address = word
+                .checked_mul(size)
+                .ok_or_else(|| anyhow!("address {} is above 64 KiB", addresses))?;
+            put_bytes(image, address, &word_bytes(value, size as usize, endian))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::LineKind;
+
+    #[test]
+    fn can_flatten_image() -> Result<()> {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x10,
+            bytes: vec![0x11, 0x22],
+        });
+        image.push(Segment {
+            address: 0x13,
+            bytes: vec![0x33],
+        });
+
+        assert!(flatten(&image, None, None).is_err());
+        assert_eq!(
+            flatten(&image, None, Some(0xff))?,
+            vec![0x11, 0x22, 0xff, 0x33]
+        );
+        assert_eq!(
+            flatten(&image, Some(0x0e), Some(0x00))?,
+            vec![0x00, 0x00, 0x11, 0x22, 0x00, 0x33]
+        );
+        assert!(flatten(&image, Some(0x11), Some(0x00)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_write_binary() -> Result<()> {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x10,
+            bytes: vec![0x11, 0x22],
+        });
+        let args = FormatArgs::new(Format::Binary);
+        let mut output: Vec<u8> = Vec::new();
+        write(&image, &[], &[], "a", &args, &mut output)?;
+
+        assert_eq!(output, vec![0x11, 0x22]);
+        Ok(())
+    }
+
+    #[test]
+    fn can_write_ihex() -> Result<()> {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x0100,
+            bytes: (0..18).collect(),
+        });
+        image.push(Segment {
+            address: 0x8000,
+            bytes: vec![0x6c, 0x6c],
+        });
+        let mut output: Vec<u8> = Vec::new();
+        write_ihex(&image, &mut output)?;
+
+        let expect = "\
+:10010000000102030405060708090A0B0C0D0E0F77
+:020110001011CC
+:028000006C6CA6
+:00000001FF
+";
+        assert_eq!(std::str::from_utf8(&output)?, expect);
+        Ok(())
+    }
+
+    #[test]
+    fn can_write_srec() -> Result<()> {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x0000,
+            bytes: vec![0x31, 0x10],
+        });
+        image.push(Segment {
+            address: 0x8000,
+            bytes: vec![0x6c, 0x6c],
+        });
+        let label_table = vec![Label {
+            name: "main".to_string(),
+            section: ".text".to_string(),
+            address: 0x0040,
+            binding: Binding::Global,
+        }];
+        let mut output: Vec<u8> = Vec::new();
+        let entry = entry(&image, &label_table, Some("main"))?;
+        write_srec(&image, "hello", entry, &mut output)?;
+
+        let expect = "\
+S008000068656C6C6FE3
+S10500003110B9
+S10580006C6CA2
+S5030002FA
+S9030040BC
+";
+        assert_eq!(std::str::from_utf8(&output)?, expect);
+        assert!(super::entry(&image, &label_table, Some("start")).is_err());
+        assert_eq!(super::entry(&image, &label_table, None)?, 0x0000);
+        Ok(())
+    }
+
+    #[test]
+    fn can_write_verilog() -> Result<()> {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x0000,
+            bytes: vec![0x31, 0x10, 0x32, 0x00],
+        });
+        image.push(Segment {
+            address: 0x0004,
+            bytes: vec![0x6c],
+        });
+        image.push(Segment {
+            address: 0x0010,
+            bytes: vec![0xff, 0xff],
+        });
+        let lines = vec![
+            Line {
+                address: 0x0000,
+                size: 2,
+                kind: LineKind::Inst,
+                file: "a.asm".to_string(),
+                line_num: 2,
+                column: 2,
+                text: "lil x1, msg@l".to_string(),
+            },
+            Line {
+                address: 0x0002,
+                size: 2,
+                kind: LineKind::Inst,
+                file: "a.asm".to_string(),
+                line_num: 3,
+                column: 2,
+                text: "lih x1, msg@h".to_string(),
+            },
+        ];
+        let mut args = FormatArgs {
+            word_width: 16,
+            comments: true,
+            ..FormatArgs::new(Format::Verilog)
+        };
+        let mut output: Vec<u8> = Vec::new();
+        write_verilog(&image, &lines, &args, &mut output)?;
+
+        let expect = "\
+@0
+1031 // lil x1, msg@l
+0032 // lih x1, msg@h
+@2
+006c
+@8
+ffff
+";
+        assert_eq!(std::str::from_utf8(&output)?, expect);
+
+        args.word_width = 8;
+        args.endian = Endian::Big;
+        args.comments = false;
+        let mut output: Vec<u8> = Vec::new();
+        write_verilog(&image, &lines, &args, &mut output)?;
+
+        let expect = "@0\n31\n10\n32\n00\n@4\n6c\n@10\nff\nff\n";
+        assert_eq!(std::str::from_utf8(&output)?, expect);
+        Ok(())
+    }
+
+    #[test]
+    fn can_write_coe_and_mif() -> Result<()> {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x0000,
+            bytes: vec![0x31, 0x10, 0x32, 0x00, 0x6c],
+        });
+        let mut args = FormatArgs {
+            word_width: 16,
+            depth: Some(6),
+            ..FormatArgs::new(Format::Coe)
+        };
+        let words = words(&image, &args)?;
+        assert_eq!(words, vec![0x1031, 0x0032, 0x006c, 0, 0, 0]);
+
+        let mut output: Vec<u8> = Vec::new();
+        write_coe(&words, &args, &mut output)?;
+        let expect = "\
+memory_initialization_radix=16;
+memory_initialization_vector=
+1031,
+0032,
+006c,
+0000,
+0000,
+0000;
+";
+        assert_eq!(std::str::from_utf8(&output)?, expect);
+
+        args.radix = Radix::Bin;
+        let mut output: Vec<u8> = Vec::new();
+        write_mif(&words, "rom", &args, &mut output)?;
+        let expect = "\
+-- rom
+WIDTH=16;
+DEPTH=6;
+
+ADDRESS_RADIX=HEX;
+DATA_RADIX=BIN;
+
+CONTENT BEGIN
+\t0 : 0001000000110001;
+\t1 : 0000000000110010;
+\t2 : 0000000001101100;
+\t[3..5] : 0000000000000000;
+END;
+";
+        assert_eq!(std::str::from_utf8(&output)?, expect);
+
+        args.depth = Some(2);
+        assert!(super::words(&image, &args).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn can_pad_image() -> Result<()> {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x0000,
+            bytes: vec![0x31, 0x10],
+        });
+        image.push(Segment {
+            address: 0x0004,
+            bytes: vec![0x6c, 0x6c],
+        });
+        let label_table = vec![
+            Label {
+                name: "start".to_string(),
+                section: ".text".to_string(),
+                address: 0x0000,
+                binding: Binding::Local,
+            },
+            Label {
+                name: "msg".to_string(),
+                section: ".data".to_string(),
+                address: 0x0004,
+                binding: Binding::Local,
+            },
+        ];
+        let mut args = FormatArgs {
+            gap_fill: Some(0xff),
+            image_size: Some(8),
+            ..FormatArgs::new(Format::Binary)
+        };
+        check_size(&image, &label_table, &args)?;
+        let mut output: Vec<u8> = Vec::new();
+        write(&image, &label_table, &[], "a", &args, &mut output)?;
+        assert_eq!(output, vec![0x31, 0x10, 0xff, 0xff, 0x6c, 0x6c, 0xff, 0xff]);
+
+        args.image_size = None;
+        args.max_size = Some(4);
+        let err = check_size(&image, &label_table, &args).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "image is 6 bytes, 2 more than the limit of 4 bytes (over the limit from: msg)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn can_read_written_formats() -> Result<()> {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x0010,
+            bytes: vec![0x31, 0x10, 0x32, 0x00],
+        });
+        image.push(Segment {
+            address: 0x0020,
+            bytes: vec![0x6c, 0x6c],
+        });
+        let mut flat = Image::default();
+        flat.push(Segment {
+            address: 0x0000,
+            bytes: flatten(&image, Some(0), Some(0))?,
+        });
+
+        for format in [
+            Format::Mem,
+            Format::Binary,
+            Format::Ihex,
+            Format::Srec,
+            Format::Verilog,
+            Format::Coe,
+            Format::Mif,
+        ] {
+            let args = FormatArgs {
+                gap_fill: Some(0),
+                start_address: Some(0),
+                word_width: 16,
+                endian: Endian::Big,
+                comments: true,
+                radix: Radix::Bin,
+                ..FormatArgs::new(format)
+            };
+            let mut output: Vec<u8> = Vec::new();
+            write(&image, &[], &[], "a", &args, &mut output)?;
+            let result = read(&output, format, 0, Endian::Big, 16)?;
+
+            let expect = match format {
+                Format::Ihex | Format::Srec | Format::Verilog => &image,
+                _ => &flat,
+            };
+            assert_eq!(&result, expect, "{:?}", format);
+        }
+
+        assert!(read(b":0100000031CF\n", Format::Ihex, 0, Endian::Little, 8).is_err());
+        // malformed files are errors, not panics
+        for (data, format) in [
+            (&b":0\xc3\xa90\n"[..], Format::Ihex),
+            (b"S\xc3\xa9000\n", Format::Srec),
+            (b"@ffffffff\n0000\n", Format::Verilog),
+            (
+                b"memory_initialization_radix=1;\nmemory_initialization_vector=0;\n",
+                Format::Coe,
+            ),
+            (b"CONTENT BEGIN\nFFFFFFFF : 0000;\nEND;\n", Format::Mif),
+            (
+                b"WIDTH=16;\nCONTENT BEGIN\n[80000000..80000001] : 0000;\nEND;\n",
+                Format::Mif,
+            ),
+        ] {
+            assert!(
+                read(data, format, 0, Endian::Little, 8).is_err(),
+                "{:?}",
+                format
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn can_write_file_back() -> Result<()> {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x0000,
+            bytes: vec![0x31, 0x10, 0x32, 0x00, 0x6c, 0x6c],
+        });
+        for args in [
+            FormatArgs {
+                entry: Some("0x0004".to_string()),
+                ..FormatArgs::new(Format::Srec)
+            },
+            FormatArgs {
+                word_width: 16,
+                ..FormatArgs::new(Format::Verilog)
+            },
+            FormatArgs {
+                word_width: 16,
+                depth: Some(8),
+                radix: Radix::Bin,
+                ..FormatArgs::new(Format::Coe)
+            },
+            FormatArgs {
+                word_width: 16,
+                depth: Some(8),
+                radix: Radix::Dec,
+                ..FormatArgs::new(Format::Mif)
+            },
+        ] {
+            let mut output: Vec<u8> = Vec::new();
+            write(&image, &[], &[], "rom", &args, &mut output)?;
+            let (result, info) = read_with_info(&output, args.format, 0, Endian::Little, 16)?;
+
+            let mut again = FormatArgs::new(args.format);
+            info.apply(&mut again)?;
+            let mut rewritten: Vec<u8> = Vec::new();
+            let name = info.name.unwrap_or_default();
+            write(&result, &[], &[], &name, &again, &mut rewritten)?;
+            assert_eq!(
+                std::str::from_utf8(&rewritten)?,
+                std::str::from_utf8(&output)?,
+                "{:?}",
+                args.format
+            );
+        }
+
+        let octal = b"WIDTH=8;\nDATA_RADIX=OCT;\nCONTENT BEGIN\n0 : 17;\nEND;\n";
+        let (_, info) = read_with_info(octal, Format::Mif, 0, Endian::Little, 8)?;
+        assert!(info.apply(&mut FormatArgs::new(Format::Mif)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn can_write_lanes() -> Result<()> {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x0100,
+            bytes: vec![0x31, 0x10, 0x32, 0x00, 0x6c],
+        });
+        let dir = std::env::temp_dir().join(format!("zktc-lanes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("rom.mem");
+        let args = FormatArgs {
+            start_address: Some(0x0100),
+            lanes: 2,
+            ..FormatArgs::new(Format::Mem)
+        };
+        write_file(&image, &[], &[], "rom", &args, &path)?;
+        let lane_0 = std::fs::read_to_string(lane_path(&path, 0))?;
+        let lane_1 = std::fs::read_to_string(lane_path(&path, 1))?;
+
+        assert_eq!(lane_0, "31\n32\n6c\n");
+        assert_eq!(lane_1, "10\n00\n");
+
+        // --image-size is the size of the whole image, split between the lanes
+        let args = FormatArgs {
+            image_size: Some(8),
+            gap_fill: Some(0xff),
+            ..args
+        };
+        write_file(&image, &[], &[], "rom", &args, &path)?;
+        let lane_1 = std::fs::read_to_string(lane_path(&path, 1))?;
+        let odd = FormatArgs {
+            image_size: Some(7),
+            ..args
+        };
+        let err = write_file(&image, &[], &[], "rom", &odd, &path).unwrap_err();
+        assert_eq!(lane_1, "10\n00\nff\nff\n");
+        assert_eq!(
+            err.to_string(),
+            "--image-size must be a multiple of --lanes"
+        );
+
+        // without --start-address every lane starts at the start of the image
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x0101,
+            bytes: vec![0x31, 0x10, 0x32, 0x00, 0x6c],
+        });
+        let args = FormatArgs {
+            lanes: 2,
+            ..FormatArgs::new(Format::Mem)
+        };
+        write_file(&image, &[], &[], "rom", &args, &path)?;
+        let lane_0 = std::fs::read_to_string(lane_path(&path, 0))?;
+        let lane_1 = std::fs::read_to_string(lane_path(&path, 1))?;
+        std::fs::remove_dir_all(&dir)?;
+
+        assert_eq!(lane_0, "00\n10\n00\n");
+        assert_eq!(lane_1, "31\n32\n6c\n");
+        Ok(())
+    }
+
+    #[test]
+    fn can_name_lanes() {
+        assert_eq!(
+            lane_path(Path::new("out/rom.mem"), 1),
+            PathBuf::from("out/rom_1.mem")
+        );
+        assert_eq!(lane_path(Path::new("rom"), 0), PathBuf::from("rom_0"));
+    }
+}
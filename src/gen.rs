@@ -1,10 +1,85 @@
-use crate::parse::{ConstType, Expr, InstType, Label};
+use crate::image::{Image, Segment};
+use crate::object::{relocate, Object, ObjectSection, RelocKind, Relocation};
+use crate::parse::{Binding, ConstType, Expr, InstType, Label, Section, SectionKind};
 use anyhow::{anyhow, Context, Result};
 
-pub fn gen(exprs: Vec<Expr>, label_table: Vec<Label>) -> Result<Vec<u8>> {
+/// Generates the image of placed sections. Every label must be defined.
+pub fn gen(sections: Vec<Section>, label_table: Vec<Label>) -> Result<Image> {
+    let mut image = Image::default();
+
+    for section in sections {
+        // nobits sections only reserve space
+        if section.kind == SectionKind::Nobits {
+            continue;
+        }
+        for segment in gen_section(section.exprs, &section.name, &label_table, None)? {
+            image.push(segment);
+        }
+    }
+
+    Ok(image)
+}
+
+/// Generates a relocatable object from unplaced sections. References that
+/// depend on where sections are placed are left to the linker.
+pub fn gen_object(sections: Vec<Section>, label_table: Vec<Label>) -> Result<Object> {
+    let mut object = Object::default();
+
+    for section in sections {
+        let bytes = if section.kind == SectionKind::Nobits {
+            Vec::new()
+        } else {
+            let segments = gen_section(
+                section.exprs,
+                &section.name,
+                &label_table,
+                Some(&mut object.relocations),
+            )?;
+            // gaps left by .org are filled as objects hold contiguous bytes
+            let mut bytes = vec![0; section.size as usize];
+            for segment in segments {
+                let offset = segment.address as usize;
+                bytes[offset..offset + segment.bytes.len()].copy_from_slice(&segment.bytes);
+            }
+            bytes
+        };
+        object.sections.push(ObjectSection {
+            name: section.name,
+            kind: section.kind,
+            size: section.size,
+            bytes,
+            lines: section.lines,
+        });
+    }
+    object.symbols = label_table;
+
+    Ok(object)
+}
+
+/// Generates the bytes of a section as contiguous segments, which are split
+/// where `.org` skips a range.
+fn gen_section(
+    exprs: Vec<Expr>,
+    section: &str,
+    label_table: &[Label],
+    mut relocations: Option<&mut Vec<Relocation>>,
+) -> Result<Vec<Segment>> {
+    let mut segments: Vec<Segment> = Vec::new();
     let mut bytes: Vec<u8> = Vec::new();
+    let mut start: u16 = 0;
 
     'outer: for expr in exprs {
+        if let Some(address) = expr.address() {
+            if bytes.is_empty() {
+                start = address;
+            } else if start as u32 + bytes.len() as u32 != address as u32 {
+                segments.push(Segment {
+                    address: start,
+                    bytes: std::mem::take(&mut bytes),
+                });
+                start = address;
+            }
+        }
         match expr {
             Expr::Inst {
                 inst_type,
@@ -68,6 +143,19 @@ pub fn gen(exprs: Vec<Expr>, label_table: Vec<Label>) -> Result<Vec<u8>> {
                             | (imm as u16 & 0x001F) << 11;
                         bytes.push((word & 0x00FF) as u8);
                         bytes.push(((word & 0xFF00) >> 8) as u8);
+                    } else if is_branch(&mnemonic) && imm.parse::<i8>().is_err() {
+                        let word: u16 = opcode & 0x001F | (rd & 0x0007) << 5 | (rs & 0x0007) << 8;
+                        let word = gen_label(
+                            word,
+                            &imm,
+                            RelocKind::Branch,
+                            address,
+                            section,
+                            label_table,
+                            relocations.as_deref_mut(),
+                        )?;
+                        bytes.push((word & 0x00FF) as u8);
+                        bytes.push(((word & 0xFF00) >> 8) as u8);
                     } else {
                         let imm = imm
                             .parse::<i8>()
@@ -91,69 +179,57 @@ pub fn gen(exprs: Vec<Expr>, label_table: Vec<Label>) -> Result<Vec<u8>> {
                         _ => unreachable!(),
                     };
                     let rd = gen_reg(rd)?;
+                    let word: u16 = opcode & 0x001F | (rd & 0x0007) << 5;
 
                     if mnemonic.as_str() == "jal" {
-                        for label in &label_table {
-                            if imm == label.name {
-                                let imm = label.address as i32 - address as i32;
-                                if !(-128..=127).contains(&imm) {
-                                    return Err(anyhow!("could not jump to {}", label.name));
-                                }
-                                let word: u16 = opcode & 0x001F
-                                    | (rd & 0x0007) << 5
-                                    | (imm as u16 & 0x00FF) << 8;
-                                bytes.push((word & 0x00FF) as u8);
-                                bytes.push(((word & 0xFF00) >> 8) as u8);
-
-                                continue 'outer;
-                            }
+                        if let Ok(imm) = imm.parse::<i8>() {
+                            let word: u16 = word | (imm as u16 & 0x00FF) << 8;
+                            bytes.push((word & 0x00FF) as u8);
+                            bytes.push(((word & 0xFF00) >> 8) as u8);
+                            continue 'outer;
                         }
-                        let imm = imm
-                            .parse::<i8>()
-                            .with_context(|| format!("could not parse {}", imm))?;
-                        let word: u16 =
-                            opcode & 0x001F | (rd & 0x0007) << 5 | (imm as u16 & 0x00FF) << 8;
+                        let word = gen_label(
+                            word,
+                            &imm,
+                            RelocKind::Jal,
+                            address,
+                            section,
+                            label_table,
+                            relocations.as_deref_mut(),
+                        )?;
                         bytes.push((word & 0x00FF) as u8);
                         bytes.push(((word & 0xFF00) >> 8) as u8);
                     } else if imm.starts_with("0x") {
                         let imm = u16::from_str_radix(imm.trim_start_matches("0x"), 16)
                             .with_context(|| format!("could not parse {}", imm))?;
                         if symbol.as_str() == "l" {
-                            let word: u16 =
-                                opcode & 0x001F | (rd & 0x0007) << 5 | (imm & 0x00FF) << 8;
+                            let word: u16 = word | (imm & 0x00FF) << 8;
                             bytes.push((word & 0x00FF) as u8);
                             bytes.push(((word & 0xFF00) >> 8) as u8);
-                            continue 'outer;
                         } else if symbol.as_str() == "h" {
-                            let word: u16 = opcode & 0x001F | (rd & 0x0007) << 5 | (imm & 0xFF00);
+                            let word: u16 = word | (imm & 0xFF00);
                             bytes.push((word & 0x00FF) as u8);
                             bytes.push(((word & 0xFF00) >> 8) as u8);
-                            continue 'outer;
                         } else {
                             return Err(anyhow!("Unknown symbol {}", symbol));
                         }
                     } else {
-                        for label in &label_table {
-                            if imm == label.name {
-                                let imm = label.address;
-                                if symbol.as_str() == "l" {
-                                    let word: u16 =
-                                        opcode & 0x001F | (rd & 0x0007) << 5 | (imm & 0x00FF) << 8;
-                                    bytes.push((word & 0x00FF) as u8);
-                                    bytes.push(((word & 0xFF00) >> 8) as u8);
-                                    continue 'outer;
-                                } else if symbol.as_str() == "h" {
-                                    let word: u16 =
-                                        opcode & 0x001F | (rd & 0x0007) << 5 | (imm & 0xFF00);
-                                    bytes.push((word & 0x00FF) as u8);
-                                    bytes.push(((word & 0xFF00) >> 8) as u8);
-                                    continue 'outer;
-                                } else {
-                                    return Err(anyhow!("Unknown symbol {}", symbol));
-                                }
-                            }
-                        }
-                        return Err(anyhow!("Unknown label {}", imm));
+                        let kind = match symbol.as_str() {
+                            "l" => RelocKind::Low,
+                            "h" => RelocKind::High,
+                            _ => return Err(anyhow!("Unknown symbol {}", symbol)),
+                        };
+                        let word = gen_label(
+                            word,
+                            &imm,
+                            kind,
+                            address,
+                            section,
+                            label_table,
+                            relocations.as_deref_mut(),
+                        )?;
+                        bytes.push((word & 0x00FF) as u8);
+                        bytes.push(((word & 0xFF00) >> 8) as u8);
                     }
                 }
                 InstType::C1 => {
@@ -211,11 +287,61 @@ pub fn gen(exprs: Vec<Expr>, label_table: Vec<Label>) -> Result<Vec<u8>> {
                     bytes.push(byte);
                 }
             }
+            Expr::Space { size, .. } => {
+                bytes.resize(bytes.len() + size as usize, 0);
+            }
             _ => unreachable!(),
         }
     }
+    if !bytes.is_empty() {
+        segments.push(Segment {
+            address: start,
+            bytes,
+        });
+    }
 
-    Ok(bytes)
+    Ok(segments)
+}
+
+/// Fills the immediate field of `word` with label `name`. When generating an
+/// object, references to other sections or to the absolute address are
+/// recorded in `relocations` instead.
+fn gen_label(
+    word: u16,
+    name: &str,
+    kind: RelocKind,
+    address: u16,
+    section: &str,
+    label_table: &[Label],
+    relocations: Option<&mut Vec<Relocation>>,
+) -> Result<u16> {
+    let label = label_table
+        .iter()
+        .find(|l| l.name == name && l.binding != Binding::Extern);
+    let pc_relative = kind == RelocKind::Jal || kind == RelocKind::Branch;
+
+    match (label, relocations) {
+        (Some(label), None) => relocate(&kind, word, label.address, address)
+            .with_context(|| format!("could not jump to {}", name)),
+        (Some(label), Some(_)) if pc_relative && label.section == section => {
+            relocate(&kind, word, label.address, address)
+                .with_context(|| format!("could not jump to {}", name))
+        }
+        (_, Some(relocations)) if label_table.iter().any(|l| l.name == name) => {
+            relocations.push(Relocation {
+                section: section.to_string(),
+                offset: address,
+                kind,
+                symbol: name.to_string(),
+            });
+            Ok(word)
+        }
+        _ => Err(anyhow!("Unknown label {}", name)),
+    }
+}
+
+fn is_branch(mnemonic: &str) -> bool {
+    matches!(mnemonic, "beq" | "bnq" | "blt" | "bge" | "bltu" | "bgeu")
 }
 
 fn gen_reg(reg: String) -> Result<u16> {
@@ -235,6 +361,7 @@ fn gen_reg(reg: String) -> Result<u16> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::layout::{place, Layout, Location, Placement};
     use crate::parse::parse;
     use std::io::{BufReader, Read};
 
@@ -242,8 +369,8 @@ mod test {
     fn can_gen_r_inst() -> Result<()> {
         let text = load_test_asm("test/asm/r_inst_test.asm");
 
-        let (exprs, label_table) = parse(text, 0)?;
-        let result_bytes = gen(exprs, label_table)?;
+        let (sections, label_table) = parse(text)?;
+        let result_bytes = gen(sections, label_table)?.to_bytes(0);
         let expect_bytes: Vec<u8> = vec![
             0b0000_0000,
             0b0000_1000,
@@ -274,8 +401,8 @@ mod test {
     fn can_gen_i5_inst() -> Result<()> {
         let text = load_test_asm("test/asm/i5_inst_test.asm");
 
-        let (exprs, label_table) = parse(text, 0)?;
-        let result_bytes = gen(exprs, label_table)?;
+        let (sections, label_table) = parse(text)?;
+        let result_bytes = gen(sections, label_table)?.to_bytes(0);
         let expect_bytes: Vec<u8> = vec![
             0b0000_0001,
             0b0000_1000,
@@ -316,8 +443,8 @@ mod test {
     fn can_gen_i8_inst() -> Result<()> {
         let text = load_test_asm("test/asm/i8_inst_test.asm");
 
-        let (exprs, label_table) = parse(text, 0)?;
-        let result_bytes = gen(exprs, label_table)?;
+        let (sections, label_table) = parse(text)?;
+        let result_bytes = gen(sections, label_table)?.to_bytes(0);
         let expect_bytes: Vec<u8> = vec![
             0b0001_0000,
             0b0000_0001,
@@ -335,8 +462,8 @@ mod test {
     fn can_gen_c1_inst() -> Result<()> {
         let text = load_test_asm("test/asm/c1_inst_test.asm");
 
-        let (exprs, label_table) = parse(text, 0)?;
-        let result_bytes = gen(exprs, label_table)?;
+        let (sections, label_table) = parse(text)?;
+        let result_bytes = gen(sections, label_table)?.to_bytes(0);
         let expect_bytes: Vec<u8> = vec![
             0b0001_1110,
             0b0000_1000,
@@ -379,8 +506,8 @@ mod test {
     fn can_gen_c2_inst() -> Result<()> {
         let text = load_test_asm("test/asm/c2_inst_test.asm");
 
-        let (exprs, label_table) = parse(text, 0)?;
-        let result_bytes = gen(exprs, label_table)?;
+        let (sections, label_table) = parse(text)?;
+        let result_bytes = gen(sections, label_table)?.to_bytes(0);
         let expect_bytes: Vec<u8> = vec![
             0b0001_1111,
             0b0000_1000,
@@ -398,8 +525,8 @@ mod test {
     fn can_gen_trap_inst() -> Result<()> {
         let text = load_test_asm("test/asm/trap_inst_test.asm");
 
-        let (exprs, label_table) = parse(text, 0)?;
-        let result_bytes = gen(exprs, label_table)?;
+        let (sections, label_table) = parse(text)?;
+        let result_bytes = gen(sections, label_table)?.to_bytes(0);
         let expect_bytes: Vec<u8> = vec![0b1111_1111, 0b1111_1111];
 
         assert_eq!(result_bytes, expect_bytes);
@@ -410,8 +537,8 @@ mod test {
     fn can_gen_direcitve() -> Result<()> {
         let text = load_test_asm("test/asm/directive_test.asm");
 
-        let (exprs, label_table) = parse(text, 0)?;
-        let result_bytes = gen(exprs, label_table)?;
+        let (sections, label_table) = parse(text)?;
+        let result_bytes = gen(sections, label_table)?.to_bytes(0);
         let expect_bytes: Vec<u8> = vec![
             0b1111_1111,
             0b1111_1111,
@@ -426,6 +553,114 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn can_gen_section() -> Result<()> {
+        let text = load_test_asm("test/asm/section_test.asm");
+
+        let (mut sections, mut label_table) = parse(text)?;
+        let mut layout = Layout::new(0);
+        layout.add(Placement {
+            section: ".data".to_string(),
+            location: Location::Address(0x10),
+        });
+        place(&layout, &mut sections, &mut label_table)?;
+        let result_segments = gen(sections, label_table)?.segments;
+        let expect_segments: Vec<Segment> = vec![
+            Segment {
+                address: 0x00,
+                bytes: vec![
+                    0b0011_0001,
+                    0b0001_0000,
+                    0b0011_0010,
+                    0b0000_0000,
+                    0b0001_0000,
+                    0b1111_1100,
+                ],
+            },
+            Segment {
+                address: 0x10,
+                bytes: vec![0b0110_1100, 0b0110_1100],
+            },
+            Segment {
+                address: 0x22,
+                bytes: vec![0b0000_0001, 0b0000_0000],
+            },
+        ];
+
+        assert_eq!(result_segments, expect_segments);
+        Ok(())
+    }
+
+    #[test]
+    fn can_gen_object() -> Result<()> {
+        let text = load_test_asm("test/asm/reloc_test.asm");
+
+        let (sections, label_table) = parse(text)?;
+        let object = gen_object(sections, label_table)?;
+        let expect_bytes: Vec<u8> = vec![
+            0b0110_0011,
+            0b0011_0000,
+            0b0011_0000,
+            0b0000_0000,
+            0b0111_0001,
+            0b0000_0000,
+            0b0001_0000,
+            0b1111_1010,
+        ];
+        let expect_relocations: Vec<Relocation> = vec![
+            Relocation {
+                section: ".text".to_string(),
+                offset: 2,
+                kind: RelocKind::Jal,
+                symbol: "putc".to_string(),
+            },
+            Relocation {
+                section: ".text".to_string(),
+                offset: 4,
+                kind: RelocKind::Low,
+                symbol: "msg".to_string(),
+            },
+        ];
+
+        assert_eq!(object.sections[0].bytes, expect_bytes);
+        assert_eq!(object.relocations, expect_relocations);
+        Ok(())
+    }
+
+    #[test]
+    fn can_gen_org() -> Result<()> {
+        let text = load_test_asm("test/asm/org_test.asm");
+
+        let (sections, label_table) = parse(text)?;
+        let result_segments = gen(sections, label_table)?.segments;
+        let expect_segments: Vec<Segment> = vec![
+            Segment {
+                address: 0x00,
+                bytes: vec![0b1111_1111, 0b1111_1111],
+            },
+            Segment {
+                address: 0x10,
+                bytes: vec![0b0001_0001],
+            },
+        ];
+
+        assert_eq!(result_segments, expect_segments);
+        assert!(parse("\ttrap\n\t.org 0x1\n".to_string()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn can_gen_equ() -> Result<()> {
+        let text = load_test_asm("test/asm/equ_test.asm");
+
+        let (sections, label_table) = parse(text)?;
+        let result_bytes = gen(sections, label_table)?.to_bytes(0);
+        let expect_bytes: Vec<u8> = vec![0b0011_0001, 0x10, 0b0011_0010, 0x80];
+
+        assert_eq!(result_bytes, expect_bytes);
+        Ok(())
+    }
+
     fn load_test_asm(path: &str) -> String {
         let file = std::fs::File::open(path).unwrap();
         let mut reader = BufReader::new(file);
@@ -0,0 +1,270 @@
+use crate::file;
+use crate::image::Image;
+use crate::parse::{Binding, Label};
+use anyhow::{anyhow, Result};
+use std::io::Write;
+
+/// C and Rust sources to write with the exported symbols.
+#[derive(clap::Args)]
+pub struct HeaderArgs {
+    /// write a C header with the exported labels and the constants
+    #[arg(long = "c-header")]
+    pub c_header: Option<std::path::PathBuf>,
+
+    /// write a Rust module with the exported labels and the constants
+    #[arg(long = "rust-module")]
+    pub rust_module: Option<std::path::PathBuf>,
+
+    /// also put the image into the header as a byte array
+    #[arg(long = "header-image")]
+    pub header_image: bool,
+}
+
+impl HeaderArgs {
+    /// Writes the requested headers. `name` is the module name used for the
+    /// include guard and the image array.
+    pub fn write(&self, image: &Image, label_table: &[Label], name: &str) -> Result<()> {
+        let image = if self.header_image { Some(image) } else { None };
+        if let Some(path) = &self.c_header {
+            let mut output: Vec<u8> = Vec::new();
+            write_c(image, label_table, name, &mut output)?;
+            file::write(path, &output)?;
+        }
+        if let Some(path) = &self.rust_module {
+            let mut output: Vec<u8> = Vec::new();
+            write_rust(image, label_table, &mut output)?;
+            file::write(path, &output)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the identifiers and addresses of the global labels and all
+/// constants, sorted by address. `reserved` are the identifiers the header
+/// defines itself.
+fn exported(label_table: &[Label], reserved: &[String]) -> Result<Vec<(String, u16)>> {
+    let mut labels: Vec<&Label> = label_table
+        .iter()
+        .filter(|l| l.binding == Binding::Global || l.is_constant())
+        .collect();
+    labels.sort_by_key(|l| (l.address, l.name.clone()));
+
+    let mut exported: Vec<(String, &Label)> = Vec::new();
+    for label in labels {
+        let name = ident(&label.name);
+        if reserved.contains(&name) {
+            return Err(anyhow!(
+                "'{}' is written as {}, which the header already defines",
+                label.name,
+                name
+            ));
+        }
+        if let Some((_, other)) = exported.iter().find(|(n, _)| *n == name) {
+            return Err(anyhow!(
+                "'{}' and '{}' are both written as {}",
+                other.name,
+                label.name,
+                name
+            ));
+        }
+        exported.push((name, label));
+    }
+    Ok(exported
+        .into_iter()
+        .map(|(name, label)| (name, label.address))
+        .collect())
+}
+
+/// Turns a name into an upper case identifier of C and Rust. Names that
+/// start with a digit get a leading `_`.
+fn ident(name: &str) -> String {
+    let ident: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    match ident.chars().next() {
+        Some(c) if !c.is_ascii_digit() => ident,
+        _ => format!("_{}", ident),
+    }
+}
+
+fn byte_rows(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(12)
+        .map(|chunk| {
+            let chunk: Vec<String> = chunk.iter().map(|b| format!("0x{:02x},", b)).collect();
+            format!("    {}", chunk.join(" "))
+        })
+        .collect()
+}
+
+pub fn write_c(
+    image: Option<&Image>,
+    label_table: &[Label],
+    name: &str,
+    output: &mut impl Write,
+) -> Result<()> {
+    let guard = format!("{}_H", ident(name));
+    let image_address = format!("{}_IMAGE_ADDRESS", ident(name));
+    let mut reserved = vec![guard.clone()];
+    if image.is_some() {
+        reserved.push(image_address.clone());
+    }
+    let exported = exported(label_table, &reserved)?;
+    let bytes = image.map(|image| image.to_bytes(0));
+    if bytes.as_ref().is_some_and(|bytes| bytes.is_empty()) {
+        return Err(anyhow!("cannot write an empty image into a C header"));
+    }
+
+    writeln!(output, "/* generated by zktc-asm, do not edit */")?;
+    writeln!(output, "#ifndef {}", guard)?;
+    writeln!(output, "#define {}", guard)?;
+    writeln!(output)?;
+    writeln!(output, "#include <stdint.h>")?;
+    writeln!(output)?;
+    for (name, address) in exported {
+        writeln!(output, "#define {} 0x{:04x}u", name, address)?;
+    }
+    if let (Some(image), Some(bytes)) = (image, bytes) {
+        let array = ident(name).to_lowercase();
+        writeln!(output)?;
+        writeln!(output, "#define {} 0x{:04x}u", image_address, image.start())?;
+        writeln!(output, "static const uint8_t {}_image[] = {{", array)?;
+        for row in byte_rows(&bytes) {
+            writeln!(output, "{}", row)?;
+        }
+        writeln!(output, "}};")?;
+    }
+    writeln!(output)?;
+    writeln!(output, "#endif")?;
+    Ok(())
+}
+
+pub fn write_rust(
+    image: Option<&Image>,
+    label_table: &[Label],
+    output: &mut impl Write,
+) -> Result<()> {
+    let reserved = match image {
+        Some(_) => vec!["IMAGE".to_string(), "IMAGE_ADDRESS".to_string()],
+        None => Vec::new(),
+    };
+    let exported = exported(label_table, &reserved)?;
+
+    writeln!(output, "// generated by zktc-asm, do not edit")?;
+    writeln!(output)?;
+    for (name, address) in exported {
+        writeln!(output, "pub const {}: u16 = 0x{:04x};", name, address)?;
+    }
+    if let Some(image) = image {
+        let bytes = image.to_bytes(0);
+        writeln!(output)?;
+        writeln!(
+            output,
+            "pub const IMAGE_ADDRESS: u16 = 0x{:04x};",
+            image.start()
+        )?;
+        writeln!(output, "pub static IMAGE: [u8; {}] = [", bytes.len())?;
+        for row in byte_rows(&bytes) {
+            writeln!(output, "{}", row)?;
+        }
+        writeln!(output, "];")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::Segment;
+
+    #[test]
+    fn can_write_headers() -> Result<()> {
+        let label_table = vec![
+            Label {
+                name: "mailbox".to_string(),
+                section: ".bss".to_string(),
+                address: 0x8000,
+                binding: Binding::Global,
+            },
+            Label {
+                name: "loop".to_string(),
+                section: ".text".to_string(),
+                address: 0x0002,
+                binding: Binding::Local,
+            },
+            Label {
+                name: "buf-size".to_string(),
+                section: "".to_string(),
+                address: 0x0010,
+                binding: Binding::Local,
+            },
+        ];
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x0100,
+            bytes: vec![0x31, 0x10],
+        });
+
+        let mut output: Vec<u8> = Vec::new();
+        write_c(Some(&image), &label_table, "fw", &mut output)?;
+        let expect = "\
+/* generated by zktc-asm, do not edit */
+#ifndef FW_H
+#define FW_H
+
+#include <stdint.h>
+
+#define BUF_SIZE 0x0010u
+#define MAILBOX 0x8000u
+
+#define FW_IMAGE_ADDRESS 0x0100u
+static const uint8_t fw_image[] = {
+    0x31, 0x10,
+};
+
+#endif
+";
+        assert_eq!(std::str::from_utf8(&output)?, expect);
+
+        let mut output: Vec<u8> = Vec::new();
+        write_rust(None, &label_table, &mut output)?;
+        let expect = "\
+// generated by zktc-asm, do not edit
+
+pub const BUF_SIZE: u16 = 0x0010;
+pub const MAILBOX: u16 = 0x8000;
+";
+        assert_eq!(std::str::from_utf8(&output)?, expect);
+        Ok(())
+    }
+
+    #[test]
+    fn cannot_write_invalid_headers() -> Result<()> {
+        let label = |name: &str| Label {
+            name: name.to_string(),
+            section: "".to_string(),
+            address: 0x0010,
+            binding: Binding::Local,
+        };
+        let mut output: Vec<u8> = Vec::new();
+        write_rust(None, &[label("1st")], &mut output)?;
+        assert!(std::str::from_utf8(&output)?.contains("pub const _1ST: u16"));
+
+        let err = write_rust(None, &[label("buf-size"), label("buf_size")], &mut output);
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "'buf-size' and 'buf_size' are both written as BUF_SIZE"
+        );
+        assert!(write_rust(Some(&Image::default()), &[label("image")], &mut output).is_err());
+        assert!(write_c(None, &[label("fw-h")], "fw", &mut output).is_err());
+        assert!(write_c(Some(&Image::default()), &[], "fw", &mut output).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+#[derive(Debug, PartialEq, Clone)]
+pub struct Segment {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+}
+
+impl Segment {
+    pub fn end(&self) -> u32 {
+        self.address as u32 + self.bytes.len() as u32
+    }
+}
+
+/// Bytes to be loaded into memory, kept as contiguous segments sorted by
+/// address so that gaps between sections are not lost.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+}
+
+impl Image {
+    pub fn push(&mut self, segment: Segment) {
+        if segment.bytes.is_empty() {
+            return;
+        }
+        let index = self
+            .segments
+            .iter()
+            .position(|s| s.address > segment.address)
+            .unwrap_or(self.segments.len());
+        self.segments.insert(index, segment);
+    }
+
+    pub fn start(&self) -> u16 {
+        self.segments.first().map_or(0, |s| s.address)
+    }
+
+    pub fn end(&self) -> u32 {
+        self.segments.iter().map(|s| s.end()).max().unwrap_or(0)
+    }
+
+    pub fn byte(&self, address: u32) -> Option<u8> {
+        self.segments
+            .iter()
+            .find(|s| s.address as u32 <= address && address < s.end())
+            .map(|s| s.bytes[(address - s.address as u32) as usize])
+    }
+
+    /// Returns the `(start, end)` ranges between segments that hold no bytes.
+    pub fn gaps(&self) -> Vec<(u16, u32)> {
+        self.segments
+            .windows(2)
+            .filter(|w| w[0].end() < w[1].address as u32)
+            .map(|w| (w[0].end() as u16, w[1].address as u32))
+            .collect()
+    }
+
+    /// Flattens the image into one byte vector starting at `start()`, filling
+    /// gaps between segments with `fill`.
+    pub fn to_bytes(&self, fill: u8) -> Vec<u8> {
+        let start = self.start() as usize;
+        let mut bytes = vec![fill; self.end() as usize - start];
+        for segment in &self.segments {
+            let offset = segment.address as usize - start;
+            bytes[offset..offset + segment.bytes.len()].copy_from_slice(&segment.bytes);
+        }
+        bytes
+    }
+
+    /// Overwrites the bytes at `address`, adding them where no segment holds
+    /// them, and joins segments that end up touching.
+    pub fn write(&mut self, address: u16, bytes: &[u8]) {
+        // runs of bytes that no segment holds, each added as one segment
+        let mut missing: Vec<Segment> = Vec::new();
+        for (i, byte) in bytes.iter().enumerate() {
+            let a = address as u32 + i as u32;
+            match self
+                .segments
+                .iter_mut()
+                .find(|s| s.address as u32 <= a && a < s.end())
+            {
+                Some(segment) => segment.bytes[(a - segment.address as u32) as usize] = *byte,
+                None => match missing.last_mut() {
+                    Some(last) if last.end() == a => last.bytes.push(*byte),
+                    _ => missing.push(Segment {
+                        address: a as u16,
+                        bytes: vec![*byte],
+                    }),
+                },
+            }
+        }
+        for segment in missing {
+            self.push(segment);
+        }
+
+        let mut segments: Vec<Segment> = Vec::new();
+        for segment in std::mem::take(&mut self.segments) {
+            match segments.last_mut() {
+                Some(last) if last.end() == segment.address as u32 => {
+                    last.bytes.extend(segment.bytes);
+                }
+                _ => segments.push(segment),
+            }
+        }
+        self.segments = segments;
+    }
+
+    /// Returns the part of the image in `start..end`.
+    pub fn slice(&self, start: u16, end: u32) -> Image {
+        let mut image = Image::default();
+        for segment in &self.segments {
+            let from = segment.address.max(start) as u32;
+            let to = segment.end().min(end);
+            if from < to {
+                let offset = (from - segment.address as u32) as usize;
+                image.push(Segment {
+                    address: from as u16,
+                    bytes: segment.bytes[offset..offset + (to - from) as usize].to_vec(),
+                });
+            }
+        }
+        image
+    }
+
+    /// Returns the bytes at addresses `index`, `index + count`, ... as an
+    /// image of its own, where the byte at `address` is moved to
+    /// `address / count`.
+    pub fn lane(&self, index: u16, count: u16) -> Image {
+        let mut image = Image::default();
+        for segment in &self.segments {
+            let skip = (index + count - segment.address % count) % count;
+            let bytes: Vec<u8> = segment
+                .bytes
+                .iter()
+                .skip(skip as usize)
+                .step_by(count as usize)
+                .copied()
+                .collect();
+            image.push(Segment {
+                address: ((segment.address as u32 + skip as u32) / count as u32) as u16,
+                bytes,
+            });
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn can_flatten_image() {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x14,
+            bytes: vec![0x33],
+        });
+        image.push(Segment {
+            address: 0x10,
+            bytes: vec![0x11, 0x22],
+        });
+
+        assert_eq!(image.start(), 0x10);
+        assert_eq!(image.end(), 0x15);
+        assert_eq!(image.gaps(), vec![(0x12, 0x14)]);
+        assert_eq!(image.byte(0x11), Some(0x22));
+        assert_eq!(image.byte(0x12), None);
+        assert_eq!(image.to_bytes(0xff), vec![0x11, 0x22, 0xff, 0xff, 0x33]);
+    }
+
+    #[test]
+    fn can_write_and_slice_image() {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x10,
+            bytes: vec![0x11, 0x22],
+        });
+        image.push(Segment {
+            address: 0x14,
+            bytes: vec![0x55],
+        });
+        image.write(0x11, &[0xaa, 0xbb, 0xcc]);
+
+        assert_eq!(
+            image.segments,
+            vec![Segment {
+                address: 0x10,
+                bytes: vec![0x11, 0xaa, 0xbb, 0xcc, 0x55],
+            }]
+        );
+        assert_eq!(
+            image.slice(0x12, 0x14).segments,
+            vec![Segment {
+                address: 0x12,
+                bytes: vec![0xbb, 0xcc],
+            }]
+        );
+    }
+
+    #[test]
+    fn can_split_image_into_lanes() {
+        let mut image = Image::default();
+        image.push(Segment {
+            address: 0x10,
+            bytes: vec![0x11, 0x22, 0x33],
+        });
+        image.push(Segment {
+            address: 0x21,
+            bytes: vec![0x44, 0x55],
+        });
+
+        let low = image.lane(0, 2);
+        assert_eq!(
+            low.segments,
+            vec![
+                Segment {
+                    address: 0x08,
+                    bytes: vec![0x11, 0x33],
+                },
+                Segment {
+                    address: 0x11,
+                    bytes: vec![0x55],
+                },
+            ]
+        );
+        let high = image.lane(1, 2);
+        assert_eq!(
+            high.segments,
+            vec![
+                Segment {
+                    address: 0x08,
+                    bytes: vec![0x22],
+                },
+                Segment {
+                    address: 0x10,
+                    bytes: vec![0x44],
+                },
+            ]
+        );
+    }
+}
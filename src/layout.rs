@@ -0,0 +1,679 @@
+use crate::parse::{is_ident, is_section_name, number, Binding, Expr, Label, Section, SectionKind};
+use anyhow::{anyhow, Context, Result};
+use clap_num::maybe_hex;
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while, take_while1},
+    character::complete::{multispace0, multispace1},
+    IResult,
+};
+use std::io::Write;
+
+/// A range of memory sections can be placed in, such as ROM or RAM.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Region {
+    pub name: String,
+    pub origin: u16,
+    pub length: u32,
+    /// any of `r`, `w` and `x`
+    pub attributes: String,
+}
+
+impl Region {
+    pub fn end(&self) -> u32 {
+        self.origin as u32 + self.length
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Location {
+    Address(u16),
+    /// the next free address of a region
+    Region(String),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Placement {
+    pub section: String,
+    pub location: Location,
+}
+
+/// Maximum size of a section.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SectionLimit {
+    pub section: String,
+    pub size: u32,
+}
+
+/// Placement rules for sections. Sections without a rule follow the previous
+/// section; the first one starts at `base_address`.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Layout {
+    pub base_address: u16,
+    pub regions: Vec<Region>,
+    pub placements: Vec<Placement>,
+}
+
+impl Layout {
+    pub fn new(base_address: u16) -> Self {
+        Layout {
+            base_address,
+            regions: Vec::new(),
+            placements: Vec::new(),
+        }
+    }
+
+    /// Adds a rule, replacing any earlier rule for the same section.
+    pub fn add(&mut self, placement: Placement) {
+        self.placements.retain(|p| p.section != placement.section);
+        self.placements.push(placement);
+    }
+}
+
+/// Where sections are placed, and how large they may grow.
+#[derive(clap::Args)]
+pub struct LayoutArgs {
+    /// base address
+    #[arg(short = 'b', default_value_t=0, value_parser=maybe_hex::<u16>)]
+    pub base_address: u16,
+
+    /// layout file declaring memory regions and placing sections
+    #[arg(short = 'T')]
+    pub layout_file: Option<std::path::PathBuf>,
+
+    /// place a section at an address (e.g. .data=0x8000)
+    #[arg(long = "section-start", value_parser=parse_placement)]
+    pub section_start: Vec<Placement>,
+
+    /// limit the size of a section (e.g. .text=0x800)
+    #[arg(long = "section-size", value_parser=parse_section_limit)]
+    pub section_size: Vec<SectionLimit>,
+
+    /// print the usage of each memory region
+    #[arg(long = "print-memory-usage")]
+    pub print_memory_usage: bool,
+}
+
+impl LayoutArgs {
+    pub fn layout(&self) -> Result<Layout> {
+        let mut layout = match &self.layout_file {
+            Some(layout_file) => {
+                let text = std::fs::read_to_string(layout_file)
+                    .with_context(|| format!("could not read file '{}'", layout_file.display()))?;
+                parse(&text, self.base_address)?
+            }
+            None => Layout::new(self.base_address),
+        };
+        for placement in &self.section_start {
+            layout.add(placement.clone());
+        }
+        Ok(layout)
+    }
+}
+
+enum Rule {
+    Region(Region),
+    Placement(Placement),
+}
+
+/// Parses a layout file. Each line declares a memory region
+/// (`region name origin length attributes`), places a section at an address
+/// (`section address`) or places it in a region (`section > region`).
+pub fn parse(text: &str, base_address: u16) -> Result<Layout> {
+    let mut layout = Layout::new(base_address);
+
+    for (line_num, line) in text.lines().enumerate() {
+        // delete empty line and comment
+        if line.trim().is_empty() || line.trim().starts_with("//") {
+            continue;
+        }
+        match parse_line(line) {
+            Ok((_, Rule::Region(region))) => {
+                if layout.regions.iter().any(|r| r.name == region.name) {
+                    return Err(anyhow!("duplicate region {}", region.name));
+                }
+                layout.regions.push(region);
+            }
+            Ok((_, Rule::Placement(placement))) => {
+                if let Location::Region(name) = &placement.location {
+                    if !layout.regions.iter().any(|r| &r.name == name) {
+                        return Err(anyhow!(
+                            "unknown region {} : line {}:{}",
+                            name,
+                            line_num + 1,
+                            line
+                        ));
+                    }
+                }
+                layout.add(placement);
+            }
+            Err(_) => {
+                return Err(anyhow!("Syntax Error : line {}:{}", line_num + 1, line));
+            }
+        }
+    }
+    Ok(layout)
+}
+
+/// Parses a `section=address` rule given on the command line.
+pub fn parse_placement(s: &str) -> Result<Placement, String> {
+    let (section, address) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected section=address, got '{}'", s))?;
+    match number(address) {
+        Ok(("", address)) if address <= 0xFFFF => Ok(Placement {
+            section: section.to_string(),
+            location: Location::Address(address as u16),
+        }),
+        _ => Err(format!("invalid address '{}'", address)),
+    }
+}
+
+/// Parses a `section=size` limit given on the command line.
+pub fn parse_section_limit(s: &str) -> Result<SectionLimit, String> {
+    let (section, size) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected section=size, got '{}'", s))?;
+    match number(size) {
+        Ok(("", size)) if size <= 0x10000 => Ok(SectionLimit {
+            section: section.to_string(),
+            size,
+        }),
+        _ => Err(format!("invalid size '{}'", size)),
+    }
+}
+
+fn parse_line(line: &str) -> IResult<&str, Rule> {
+    let (line, _) = multispace0(line)?;
+    let (line, rule) = alt((parse_region, parse_section))(line)?;
+    let (line, _) = multispace0(line)?;
+    if !(line.is_empty() || line.starts_with("//")) {
+        let err = nom::error::Error::new(line, nom::error::ErrorKind::Fail);
+        return Err(nom::Err::Error(err));
+    }
+    Ok((line, rule))
+}
+
+fn parse_region(line: &str) -> IResult<&str, Rule> {
+    let (line, _) = tag("region")(line)?;
+    let (line, _) = multispace1(line)?;
+    let (line, name) = take_while1(is_ident)(line)?;
+    let (line, _) = multispace1(line)?;
+    let (line, origin) = number(line)?;
+    let (line, _) = multispace1(line)?;
+    let (line, length) = number(line)?;
+    let (line, _) = multispace0(line)?;
+    let (line, attributes) = take_while(|c| c == 'r' || c == 'w' || c == 'x')(line)?;
+    if origin > 0xFFFF || length > 0x10000 - origin {
+        let err = nom::error::Error::new(line, nom::error::ErrorKind::Fail);
+        return Err(nom::Err::Error(err));
+    }
+    Ok((
+        line,
+        Rule::Region(Region {
+            name: name.to_string(),
+            origin: origin as u16,
+            length,
+            attributes: attributes.to_string(),
+        }),
+    ))
+}
+
+fn parse_section(line: &str) -> IResult<&str, Rule> {
+    let (line, section) = take_while1(is_section_name)(line)?;
+    let (line, _) = multispace1(line)?;
+    let (line, location) =
+        if let Ok((line, _)) = tag::<&str, &str, nom::error::Error<&str>>(">")(line) {
+            let (line, _) = multispace0(line)?;
+            let (line, region) = take_while1(is_ident)(line)?;
+            (line, Location::Region(region.to_string()))
+        } else {
+            let (line, address) = number(line)?;
+            if address > 0xFFFF {
+                let err = nom::error::Error::new(line, nom::error::ErrorKind::Fail);
+                return Err(nom::Err::Error(err));
+            }
+            (line, Location::Address(address as u16))
+        };
+    Ok((
+        line,
+        Rule::Placement(Placement {
+            section: section.to_string(),
+            location,
+        }),
+    ))
+}
+
+/// Assigns each section its address and moves its expressions and labels from
+/// section relative to absolute addresses.
+pub fn place(layout: &Layout, sections: &mut [Section], label_table: &mut [Label]) -> Result<()> {
+    let mut next: u32 = layout.base_address as u32;
+    // next free address of each region
+    let mut cursors: Vec<u32> = layout.regions.iter().map(|r| r.origin as u32).collect();
+
+    // a misspelled section would otherwise stay at its default address
+    for placement in &layout.placements {
+        if !sections.iter().any(|s| s.name == placement.section) {
+            return Err(anyhow!("unknown section {}", placement.section));
+        }
+    }
+
+    for section in sections.iter_mut() {
+        let location = layout
+            .placements
+            .iter()
+            .find(|p| p.section == section.name)
+            .map(|p| &p.location);
+        let address = match location {
+            Some(Location::Address(address)) => *address as u32,
+            Some(Location::Region(name)) => {
+                let index = layout
+                    .regions
+                    .iter()
+                    .position(|r| &r.name == name)
+                    .ok_or_else(|| anyhow!("unknown region {}", name))?;
+                let region = &layout.regions[index];
+                if section.kind == SectionKind::Nobits && !region.attributes.contains('w') {
+                    return Err(anyhow!(
+                        "nobits section {} placed in read-only region {}",
+                        section.name,
+                        region.name
+                    ));
+                }
+                let address = cursors[index];
+                cursors[index] += section.size as u32;
+                if cursors[index] > region.end() {
+                    return Err(anyhow!(
+                        "section {} overflows region {} by {} bytes",
+                        section.name,
+                        region.name,
+                        cursors[index] - region.end()
+                    ));
+                }
+                address
+            }
+            None => next,
+        };
+        if address + section.size as u32 > 0x10000 {
+            return Err(anyhow!(
+                "section {} does not fit in memory at 0x{:04x}",
+                section.name,
+                address
+            ));
+        }
+        section.address = address as u16;
+        next = address + section.size as u32;
+
+        // an empty expression or line at the end of memory has no address
+        let end_of_memory = || {
+            anyhow!(
+                "section {} at 0x{:04x} reaches the end of memory",
+                section.name,
+                address
+            )
+        };
+        for expr in section.exprs.iter_mut() {
+            match expr {
+                Expr::Inst { address, .. }
+                | Expr::Const { address, .. }
+                | Expr::Space { address, .. } => {
+                    *address = address
+                        .checked_add(section.address)
+                        .ok_or_else(end_of_memory)?;
+                }
+                _ => unreachable!(),
+            }
+        }
+        for line in section.lines.iter_mut() {
+            line.address = line
+                .address
+                .checked_add(section.address)
+                .ok_or_else(end_of_memory)?;
+        }
+    }
+
+    for label in label_table.iter_mut() {
+        if let Some(section) = sections.iter().find(|s| s.name == label.section) {
+            label.address = label
+                .address
+                .checked_add(section.address)
+                .ok_or_else(|| anyhow!("label {} is past the end of memory", label.name))?;
+        }
+    }
+
+    for (i, a) in sections.iter().enumerate() {
+        for b in sections.iter().skip(i + 1) {
+            if a.size == 0 || b.size == 0 {
+                continue;
+            }
+            let (a_end, b_end) = (
+                a.address as u32 + a.size as u32,
+                b.address as u32 + b.size as u32,
+            );
+            if (a.address as u32) < b_end && (b.address as u32) < a_end {
+                return Err(anyhow!(
+                    "section {} (0x{:04x}-0x{:04x}) overlaps section {} (0x{:04x}-0x{:04x})",
+                    a.name,
+                    a.address,
+                    a_end - 1,
+                    b.name,
+                    b.address,
+                    b_end - 1
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns how many bytes of each region are occupied by placed sections.
+pub fn usage(layout: &Layout, sections: &[Section]) -> Vec<(Region, u32)> {
+    layout
+        .regions
+        .iter()
+        .map(|region| {
+            let used = sections
+                .iter()
+                .map(|s| {
+                    let start = (s.address as u32).max(region.origin as u32);
+                    let end = (s.address as u32 + s.size as u32).min(region.end());
+                    end.saturating_sub(start)
+                })
+                .sum();
+            (region.clone(), used)
+        })
+        .collect()
+}
+
+pub fn write_usage(layout: &Layout, sections: &[Section], output: &mut impl Write) -> Result<()> {
+    writeln!(
+        output,
+        "region     origin  length   used     usage  attributes"
+    )?;
+    for (region, used) in usage(layout, sections) {
+        let percent = if region.length == 0 {
+            0.0
+        } else {
+            used as f64 * 100.0 / region.length as f64
+        };
+        writeln!(
+            output,
+            "{:<10} 0x{:04x}  0x{:05x}  0x{:05x}  {:5.1}%  {}",
+            region.name, region.origin, region.length, used, percent, region.attributes
+        )?;
+    }
+    Ok(())
+}
+
+/// Checks placed sections against their size limits.
+pub fn check_limits(
+    limits: &[SectionLimit],
+    sections: &[Section],
+    label_table: &[Label],
+) -> Result<()> {
+    for limit in limits {
+        let section = sections
+            .iter()
+            .find(|s| s.name == limit.section)
+            .ok_or_else(|| anyhow!("unknown section {}", limit.section))?;
+        if section.size as u32 > limit.size {
+            return Err(over_limit(
+                &format!("section {}", section.name),
+                section.address as u32,
+                section.size as u32,
+                limit.size,
+                label_table
+                    .iter()
+                    .filter(|l| l.section == section.name)
+                    .collect(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Builds the error for `size` bytes from `start` that exceed `limit`, naming
+/// the label that crosses the limit and the labels after it.
+pub fn over_limit(
+    what: &str,
+    start: u32,
+    size: u32,
+    limit: u32,
+    labels: Vec<&Label>,
+) -> anyhow::Error {
+    let end = start + limit;
+    let mut labels: Vec<&Label> = labels
+        .into_iter()
+        .filter(|l| l.binding != Binding::Extern && !l.is_constant())
+        .filter(|l| l.address as u32 >= start && (l.address as u32) < start + size)
+        .collect();
+    labels.sort_by_key(|l| l.address);
+    // unless a label starts right at the limit, the one before it runs past
+    let mut first = labels
+        .iter()
+        .position(|l| l.address as u32 >= end)
+        .unwrap_or(labels.len());
+    if first > 0 && labels.get(first).is_none_or(|l| l.address as u32 != end) {
+        first -= 1;
+    }
+    let names: Vec<&str> = labels.iter().skip(first).map(|l| l.name.as_str()).collect();
+
+    let mut message = format!(
+        "{} is {} bytes, {} more than the limit of {} bytes",
+        what,
+        size,
+        size - limit,
+        limit
+    );
+    if !names.is_empty() {
+        message += &format!(" (over the limit from: {})", names.join(", "));
+    }
+    anyhow!(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn can_parse_layout() -> Result<()> {
+        let text = "// layout\n.text 0x0000\n.data 0x8000 // ram\n\n.bss 32768\n";
+        let expect_placements: Vec<Placement> = vec![
+            Placement {
+                section: ".text".to_string(),
+                location: Location::Address(0x0000),
+            },
+            Placement {
+                section: ".data".to_string(),
+                location: Location::Address(0x8000),
+            },
+            Placement {
+                section: ".bss".to_string(),
+                location: Location::Address(0x8000),
+            },
+        ];
+        assert_eq!(parse(text, 0)?.placements, expect_placements);
+        assert!(parse(".text 0x10000", 0).is_err());
+        assert_eq!(
+            parse_placement(".data=0x8000"),
+            Ok(expect_placements[1].clone())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_place_section() -> Result<()> {
+        let text = ".data\nmsg:\n\t.word 0x1234\n.text\nstart:\n\ttrap\n.bss\nbuf:\n\t.space 4\n";
+        let (mut sections, mut label_table) = parse::parse(text.to_string())?;
+        let mut layout = Layout::new(0x100);
+        layout.add(Placement {
+            section: ".data".to_string(),
+            location: Location::Address(0x8000),
+        });
+        place(&layout, &mut sections, &mut label_table)?;
+
+        let addresses: Vec<(&str, u16)> = sections
+            .iter()
+            .map(|s| (s.name.as_str(), s.address))
+            .collect();
+        assert_eq!(
+            addresses,
+            vec![(".text", 0x100), (".data", 0x8000), (".bss", 0x8002)]
+        );
+        let addresses: Vec<(&str, u16)> = label_table
+            .iter()
+            .map(|l| (l.name.as_str(), l.address))
+            .collect();
+        assert_eq!(
+            addresses,
+            vec![("msg", 0x8000), ("start", 0x100), ("buf", 0x8002)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_parse_region() -> Result<()> {
+        let text = "region rom 0x0000 0x8000 rx\nregion ram 0x8000 0x7000 rw // ram\n.text > rom\n.data > ram\n";
+        let layout = parse(text, 0)?;
+        let expect_regions: Vec<Region> = vec![
+            Region {
+                name: "rom".to_string(),
+                origin: 0x0000,
+                length: 0x8000,
+                attributes: "rx".to_string(),
+            },
+            Region {
+                name: "ram".to_string(),
+                origin: 0x8000,
+                length: 0x7000,
+                attributes: "rw".to_string(),
+            },
+        ];
+        let expect_placements: Vec<Placement> = vec![
+            Placement {
+                section: ".text".to_string(),
+                location: Location::Region("rom".to_string()),
+            },
+            Placement {
+                section: ".data".to_string(),
+                location: Location::Region("ram".to_string()),
+            },
+        ];
+        assert_eq!(layout.regions, expect_regions);
+        assert_eq!(layout.placements, expect_placements);
+        assert!(parse(".text > rom", 0).is_err());
+        assert!(parse("region rom 0xff00 0x1000 rx", 0).is_err());
+        assert!(parse("region rom 0x1 0xFFFFFFFF rx", 0).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_place_section_in_region() -> Result<()> {
+        let text = ".data\n\t.word 0x1234\n.text\n\ttrap\n.bss\n\t.space 4\n";
+        let (mut sections, mut label_table) = parse::parse(text.to_string())?;
+        let layout = parse(
+            "region rom 0x0000 0x100 rx\nregion ram 0x8000 0x100 rw\n.text > rom\n.data > ram\n.bss > ram\n",
+            0,
+        )?;
+        place(&layout, &mut sections, &mut label_table)?;
+
+        let addresses: Vec<(&str, u16)> = sections
+            .iter()
+            .map(|s| (s.name.as_str(), s.address))
+            .collect();
+        assert_eq!(
+            addresses,
+            vec![(".text", 0x0000), (".data", 0x8000), (".bss", 0x8002)]
+        );
+        let usage: Vec<(String, u32)> = usage(&layout, &sections)
+            .into_iter()
+            .map(|(r, used)| (r.name, used))
+            .collect();
+        assert_eq!(usage, vec![("rom".to_string(), 2), ("ram".to_string(), 6)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cannot_overflow_region() -> Result<()> {
+        let text = "\t.space 0x101\n";
+        let layout = parse("region rom 0x0000 0x100 rx\n.text > rom\n", 0)?;
+        let (mut sections, mut label_table) = parse::parse(text.to_string())?;
+        assert!(place(&layout, &mut sections, &mut label_table).is_err());
+
+        let layout = parse("region rom 0x0000 0x100 rx\n.bss > rom\n", 0)?;
+        let (mut sections, mut label_table) = parse::parse(".bss\n\t.space 1\n".to_string())?;
+        assert!(place(&layout, &mut sections, &mut label_table).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cannot_place_label_past_end_of_memory() -> Result<()> {
+        let text = "start:\n\ttrap\nend:\n";
+        let (mut sections, mut label_table) = parse::parse(text.to_string())?;
+        let err = place(&Layout::new(0xFFFE), &mut sections, &mut label_table).unwrap_err();
+        assert_eq!(err.to_string(), "label end is past the end of memory");
+
+        let (mut sections, mut label_table) = parse::parse(text.to_string())?;
+        place(&Layout::new(0xFFFC), &mut sections, &mut label_table)?;
+        assert_eq!(label_table[1].address, 0xFFFE);
+        Ok(())
+    }
+
+    #[test]
+    fn cannot_place_unknown_section() -> Result<()> {
+        let text = ".text\n\ttrap\n.data\n\t.word 0x1234\n";
+        let (mut sections, mut label_table) = parse::parse(text.to_string())?;
+        let mut layout = Layout::new(0);
+        layout.add(Placement {
+            section: ".dta".to_string(),
+            location: Location::Address(0x8000),
+        });
+        let err = place(&layout, &mut sections, &mut label_table).unwrap_err();
+        assert_eq!(err.to_string(), "unknown section .dta");
+        Ok(())
+    }
+
+    #[test]
+    fn cannot_place_overlapping_section() -> Result<()> {
+        let text = ".text\n\ttrap\n\ttrap\n.data\n\t.word 0x1234\n";
+        let (mut sections, mut label_table) = parse::parse(text.to_string())?;
+        let mut layout = Layout::new(0);
+        layout.add(Placement {
+            section: ".data".to_string(),
+            location: Location::Address(0x0002),
+        });
+        assert!(place(&layout, &mut sections, &mut label_table).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cannot_exceed_section_size() -> Result<()> {
+        let text = "start:\n\tlil x1, 0x00@l\n\tlih x1, 0x00@h\nloop:\n\tjal zero, loop\nnext:\n\tjal zero, next\n";
+        let (mut sections, mut label_table) = parse::parse(text.to_string())?;
+        place(&Layout::new(0), &mut sections, &mut label_table)?;
+        let limit = parse_section_limit(".text=6").map_err(|e| anyhow!(e))?;
+
+        let err = check_limits(&[limit], &sections, &label_table).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "section .text is 8 bytes, 2 more than the limit of 6 bytes (over the limit from: next)"
+        );
+        let limit = parse_section_limit(".text=5").map_err(|e| anyhow!(e))?;
+        let err = check_limits(&[limit], &sections, &label_table).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "section .text is 8 bytes, 3 more than the limit of 5 bytes (over the limit from: loop, next)"
+        );
+        let limit = parse_section_limit(".text=8").map_err(|e| anyhow!(e))?;
+        assert!(check_limits(&[limit], &sections, &label_table).is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,18 @@
+pub mod boot;
+pub mod convert;
+pub mod debug;
+pub mod diff;
+pub mod disasm;
+pub mod file;
+pub mod format;
+pub mod gen;
+pub mod header;
+pub mod image;
+pub mod layout;
+pub mod link;
+pub mod listing;
+pub mod map;
+pub mod object;
+pub mod output;
+pub mod parse;
+pub mod patch;
@@ -0,0 +1,295 @@
+use crate::image::{Image, Segment};
+use crate::layout::{place, Layout};
+use crate::object::{relocate, Object};
+use crate::parse::{Binding, Label, Section, SectionKind};
+use anyhow::{anyhow, Context, Result};
+
+/// Links objects into an image and returns it with the output sections and the
+/// symbols at their final addresses. Sections with the same name are
+/// concatenated in the order the objects are given.
+pub fn link(objects: Vec<Object>, layout: &Layout) -> Result<(Image, Vec<Section>, Vec<Label>)> {
+    let mut sections: Vec<Section> = Vec::new();
+    let mut contents: Vec<Vec<u8>> = Vec::new();
+    // offsets[i][j] is where section j of object i starts in its output section
+    let mut offsets: Vec<Vec<(usize, u16)>> = Vec::new();
+
+    for object in &objects {
+        let mut object_offsets = Vec::new();
+        for object_section in &object.sections {
+            let index = match sections.iter().position(|s| s.name == object_section.name) {
+                Some(index) => index,
+                None => {
+                    let mut section = Section::new(&object_section.name);
+                    section.kind = object_section.kind.clone();
+                    sections.push(section);
+                    contents.push(Vec::new());
+                    sections.len() - 1
+                }
+            };
+            let section = &mut sections[index];
+            if section.kind != object_section.kind {
+                return Err(anyhow!(
+                    "section {} is both progbits and nobits",
+                    section.name
+                ));
+            }
+            let offset = section.size;
+            object_offsets.push((index, offset));
+            section.size = section
+                .size
+                .checked_add(object_section.size)
+                .ok_or_else(|| anyhow!("section {} overflows", section.name))?;
+            contents[index].extend_from_slice(&object_section.bytes);
+            for line in &object_section.lines {
+                let mut line = line.clone();
+                line.address += offset;
+                section.lines.push(line);
+            }
+        }
+        offsets.push(object_offsets);
+    }
+
+    place(layout, &mut sections, &mut [])?;
+
+    // resolve the symbols of every object to absolute addresses
+    let mut symbol_tables: Vec<Vec<Label>> = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        let mut symbols = Vec::new();
+        for symbol in object
+            .symbols
+            .iter()
+            .filter(|s| s.binding != Binding::Extern)
+        {
+            if symbol.is_constant() {
+                symbols.push(symbol.clone());
+                continue;
+            }
+            let j = object
+                .sections
+                .iter()
+                .position(|s| s.name == symbol.section)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "symbol {} has unknown section {}",
+                        symbol.name,
+                        symbol.section
+                    )
+                })?;
+            let (index, offset) = offsets[i][j];
+            symbols.push(Label {
+                name: symbol.name.clone(),
+                section: symbol.section.clone(),
+                address: sections[index]
+                    .address
+                    .checked_add(offset)
+                    .and_then(|a| a.checked_add(symbol.address))
+                    .ok_or_else(|| anyhow!("symbol {} is past the end of memory", symbol.name))?,
+                binding: symbol.binding.clone(),
+            });
+        }
+        symbol_tables.push(symbols);
+    }
+
+    let mut globals: Vec<Label> = Vec::new();
+    for symbol in symbol_tables.iter().flatten() {
+        if symbol.binding == Binding::Global {
+            if globals.iter().any(|g| g.name == symbol.name) {
+                return Err(anyhow!("duplicate symbol {}", symbol.name));
+            }
+            globals.push(symbol.clone());
+        }
+    }
+
+    for (i, object) in objects.iter().enumerate() {
+        for relocation in &object.relocations {
+            let target = symbol_tables[i]
+                .iter()
+                .find(|s| s.name == relocation.symbol)
+                .or_else(|| globals.iter().find(|g| g.name == relocation.symbol))
+                .ok_or_else(|| anyhow!("undefined symbol {}", relocation.symbol))?;
+            let j = object
+                .sections
+                .iter()
+                .position(|s| s.name == relocation.section)
+                .ok_or_else(|| anyhow!("relocation in unknown section {}", relocation.section))?;
+            let (index, offset) = offsets[i][j];
+            let offset = offset as usize + relocation.offset as usize;
+            let bytes = &mut contents[index];
+            if offset + 1 >= bytes.len() {
+                return Err(anyhow!(
+                    "relocation outside of section {}",
+                    relocation.section
+                ));
+            }
+            let word = bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8;
+            let address = (sections[index].address as usize + offset) as u16;
+            let word = relocate(&relocation.kind, word, target.address, address)
+                .with_context(|| format!("could not jump to {}", relocation.symbol))?;
+            bytes[offset] = (word & 0x00FF) as u8;
+            bytes[offset + 1] = ((word & 0xFF00) >> 8) as u8;
+        }
+    }
+
+    let mut image = Image::default();
+    for (section, bytes) in sections.iter().zip(contents) {
+        if section.kind == SectionKind::Progbits {
+            image.push(Segment {
+                address: section.address,
+                bytes,
+            });
+        }
+    }
+
+    Ok((
+        image,
+        sections,
+        symbol_tables.into_iter().flatten().collect(),
+    ))
+}
+
+/// Makes the labels of every file visible to the other files, as if they were
+/// all exported with `.global` and declared with `.extern` where used.
+pub fn share_labels(label_tables: &mut [Vec<Label>]) {
+    let defined: Vec<String> = label_tables
+        .iter()
+        .flatten()
+        .filter(|l| l.binding != Binding::Extern)
+        .map(|l| l.name.clone())
+        .collect();
+
+    for label_table in label_tables.iter_mut() {
+        for label in label_table.iter_mut() {
+            if label.binding == Binding::Local {
+                label.binding = Binding::Global;
+            }
+        }
+        for name in &defined {
+            if !label_table.iter().any(|l| &l.name == name) {
+                label_table.push(Label {
+                    name: name.clone(),
+                    section: "".to_string(),
+                    address: 0,
+                    binding: Binding::Extern,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gen::gen_object;
+    use crate::layout::{Location, Placement};
+    use crate::parse::parse;
+
+    #[test]
+    fn can_link_objects() -> Result<()> {
+        let main = ".extern putc\n.global start\nstart:\n\tlil a0, msg@l\n\tjal ra, putc\n.data\nmsg:\n\t.byte 0x41\n";
+        let lib = ".global putc\nputc:\n\tjalr zero, ra, 0\n.data\n\t.byte 0x42\n";
+        let mut objects = Vec::new();
+        for text in [main, lib] {
+            let (sections, label_table) = parse(text.to_string())?;
+            objects.push(gen_object(sections, label_table)?);
+        }
+        let mut layout = Layout::new(0);
+        layout.add(Placement {
+            section: ".data".to_string(),
+            location: Location::Address(0x10),
+        });
+
+        let (image, _, symbols) = link(objects, &layout)?;
+        let expect_segments: Vec<Segment> = vec![
+            Segment {
+                address: 0x00,
+                bytes: vec![
+                    0b0111_0001,
+                    0b0001_0000,
+                    0b0011_0000,
+                    0b0000_0010,
+                    0b0000_1001,
+                    0b0000_0001,
+                ],
+            },
+            Segment {
+                address: 0x10,
+                bytes: vec![0x41, 0x42],
+            },
+        ];
+        let symbols: Vec<(&str, u16)> = symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.address))
+            .collect();
+
+        assert_eq!(image.segments, expect_segments);
+        assert_eq!(
+            symbols,
+            vec![("start", 0x00), ("msg", 0x10), ("putc", 0x04)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn can_share_labels() -> Result<()> {
+        let main = "start:\n\tjal ra, putc\n";
+        let lib = "putc:\n\tjalr zero, ra, 0\n";
+        let mut sections_list = Vec::new();
+        let mut label_tables = Vec::new();
+        for text in [main, lib] {
+            let (sections, label_table) = parse(text.to_string())?;
+            sections_list.push(sections);
+            label_tables.push(label_table);
+        }
+        share_labels(&mut label_tables);
+        let mut objects = Vec::new();
+        for (sections, label_table) in sections_list.into_iter().zip(label_tables) {
+            objects.push(gen_object(sections, label_table)?);
+        }
+
+        let (image, _, _) = link(objects, &Layout::new(0))?;
+        assert_eq!(image.to_bytes(0), vec![0x30, 0x02, 0x09, 0x01]);
+        Ok(())
+    }
+
+    #[test]
+    fn can_link_constants() -> Result<()> {
+        let main = ".extern SIZE\nstart:\n\tlil a0, SIZE@l\n";
+        let lib = ".equ SIZE, 0x1234\n.global SIZE\n";
+        let mut objects = Vec::new();
+        for text in [main, lib] {
+            let (sections, label_table) = parse(text.to_string())?;
+            objects.push(gen_object(sections, label_table)?);
+        }
+
+        let (image, _, _) = link(objects, &Layout::new(0))?;
+        assert_eq!(image.to_bytes(0), vec![0x71, 0x34]);
+        Ok(())
+    }
+
+    #[test]
+    fn cannot_link_undefined_symbol() -> Result<()> {
+        let (sections, label_table) = parse(".extern putc\n\tjal ra, putc\n".to_string())?;
+        let objects = vec![gen_object(sections, label_table)?];
+
+        assert!(link(objects, &Layout::new(0)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cannot_link_relocation_outside_section() -> Result<()> {
+        let mut objects = Vec::new();
+        for text in [
+            "start:\n\ttrap\n",
+            ".extern putc\n\tjal ra, putc\n",
+            ".global putc\nputc:\n\ttrap\n",
+        ] {
+            let (sections, label_table) = parse(text.to_string())?;
+            objects.push(gen_object(sections, label_table)?);
+        }
+        objects[1].relocations[0].offset = 0xffff;
+
+        let err = link(objects, &Layout::new(0)).unwrap_err();
+        assert_eq!(err.to_string(), "relocation outside of section .text");
+        Ok(())
+    }
+}
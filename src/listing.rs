@@ -0,0 +1,136 @@
+use crate::image::Image;
+use crate::parse::{Binding, Label, Line};
+use anyhow::Result;
+use std::io::Write;
+
+/// Bytes shown on one row of the listing. Longer data continues on the
+/// following rows.
+const ROW_BYTES: u16 = 4;
+
+/// Writes a listing of `text` with the address and the bytes emitted for each
+/// source line, followed by a symbol table. `lines` and `label_table` must
+/// already have their final addresses.
+pub fn write(
+    text: &str,
+    lines: &[Line],
+    image: &Image,
+    label_table: &[Label],
+    output: &mut impl Write,
+) -> Result<()> {
+    for (line_num, source) in text.lines().enumerate() {
+        let line = lines.iter().find(|l| l.line_num == line_num + 1);
+        let line = match line {
+            Some(line) => line,
+            None => {
+                writeln!(
+                    output,
+                    "{:5}  {:4}  {:12}  {}",
+                    line_num + 1,
+                    "",
+                    "",
+                    source
+                )?;
+                continue;
+            }
+        };
+
+        let mut offset = 0;
+        loop {
+            let address = line.address + offset;
+            let size = ROW_BYTES.min(line.size - offset);
+            let bytes: Vec<String> = (address as u32..address as u32 + size as u32)
+                .filter_map(|a| image.byte(a))
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            if offset == 0 {
+                writeln!(
+                    output,
+                    "{:5}  {:04x}  {:12}  {}",
+                    line_num + 1,
+                    address,
+                    bytes.join(" "),
+                    source
+                )?;
+            } else if !bytes.is_empty() {
+                writeln!(output, "{:5}  {:04x}  {}", "", address, bytes.join(" "))?;
+            }
+            offset += size;
+            if offset >= line.size {
+                break;
+            }
+        }
+    }
+
+    writeln!(output)?;
+    writeln!(output, "Symbols:")?;
+    let mut symbols: Vec<&Label> = label_table
+        .iter()
+        .filter(|l| l.binding != Binding::Extern)
+        .collect();
+    symbols.sort_by_key(|l| (l.address, l.name.clone()));
+    for symbol in symbols {
+        let binding = match symbol.binding {
+            Binding::Global => "global",
+            _ => "local",
+        };
+        writeln!(
+            output,
+            "{:04x}  {:<8}  {:<6}  {}",
+            symbol.address, symbol.section, binding, symbol.name
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gen::gen;
+    use crate::layout::{place, Layout};
+    use crate::parse::parse;
+
+    #[test]
+    fn can_write_listing() -> Result<()> {
+        let text = "\
+start:
+\tlil x1, msg@l
+\tlih x1, msg@h
+// data
+msg:
+\t.word 0x6c6c
+\t.space 6
+";
+        let (mut sections, mut label_table) = parse(text.to_string())?;
+        place(&Layout::new(0), &mut sections, &mut label_table)?;
+        let lines: Vec<Line> = sections.iter().flat_map(|s| s.lines.clone()).collect();
+        let image = gen(sections, label_table.clone())?;
+        let mut output: Vec<u8> = Vec::new();
+        write(text, &lines, &image, &label_table, &mut output)?;
+
+        let expect = "    1                      start:
+    2  0000  31 04         \tlil x1, msg@l
+    3  0002  32 00         \tlih x1, msg@h
+    4                      // data
+    5                      msg:
+    6  0004  6c 6c         \t.word 0x6c6c
+    7  0006  00 00 00 00   \t.space 6
+       000a  00 00
+
+Symbols:
+0000  .text     local   start
+0004  .text     local   msg
+";
+        assert_eq!(std::str::from_utf8(&output)?, expect);
+
+        // the last line of memory ends at 0x10000
+        let text = "\ttrap\n";
+        let (mut sections, mut label_table) = parse(text.to_string())?;
+        place(&Layout::new(0xFFFE), &mut sections, &mut label_table)?;
+        let lines: Vec<Line> = sections.iter().flat_map(|s| s.lines.clone()).collect();
+        let image = gen(sections, label_table.clone())?;
+        let mut output: Vec<u8> = Vec::new();
+        write(text, &lines, &image, &label_table, &mut output)?;
+        assert!(std::str::from_utf8(&output)?.starts_with("    1  fffe  ff ff         \ttrap\n"));
+        Ok(())
+    }
+}
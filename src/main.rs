@@ -1,42 +1,149 @@
-use anyhow::{Context, Result};
-use std::io::{BufReader, Read, Write};
-mod gen;
-mod parse;
+use anyhow::{anyhow, Context, Result};
+use zktc_asm::{convert, diff, file, gen, layout, link, listing, object, output, parse, patch};
 
-use clap::Parser;
-use clap_num::maybe_hex;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
 #[clap(version = "0.1", author = "kkinos", about = "ZKTC assembler")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// .asm file path
-    file_path: std::path::PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// output file name
-    #[arg(short = 'o', default_value = "a.mem")]
-    output_file_name: std::path::PathBuf,
+    /// .asm file paths, assembled in order into one image, or - for stdin
+    #[arg(required = true)]
+    file_paths: Vec<std::path::PathBuf>,
 
-    /// base address
-    #[arg(short = 'b', default_value_t=0, value_parser=maybe_hex::<u16>)]
-    base_address: u16,
+    /// output file name, or - for stdout [default: a.mem, a.bin, ... by format, or a.o with -c]
+    #[arg(short = 'o')]
+    output_file_name: Option<std::path::PathBuf>,
+
+    /// write a listing with the address and bytes of each source line
+    #[arg(short = 'l')]
+    listing_file_name: Option<std::path::PathBuf>,
+
+    /// write a relocatable object for zktc-ld instead of an image
+    #[arg(short = 'c', conflicts_with_all = [
+        "listing_file_name",
+        "debug_file_name",
+        "map_file_name",
+        "map_format",
+        "LayoutArgs",
+        "FormatArgs",
+        "HeaderArgs",
+        "BootArgs",
+    ])]
+    object: bool,
+
+    /// share labels between files instead of exporting them with .global
+    #[arg(long = "shared-scope")]
+    shared_scope: bool,
+
+    #[command(flatten)]
+    output: output::OutputArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert an image between output formats
+    Convert(convert::ConvertArgs),
+    /// Assemble a snippet into an existing image
+    Patch(patch::PatchArgs),
+    /// Compare two images and show the changed instructions
+    Diff(diff::DiffArgs),
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let asm_file = std::fs::File::open(&args.file_path)
-        .with_context(|| format!("could not read file '{}'", &args.file_path.display()))?;
-    let mut reader = BufReader::new(asm_file);
-    let mut text = String::new();
-    reader.read_to_string(&mut text)?;
+    match &args.command {
+        Some(Command::Convert(convert_args)) => return convert::convert(convert_args),
+        Some(Command::Patch(patch_args)) => return patch::patch(patch_args),
+        Some(Command::Diff(diff_args)) => {
+            // exit with 1 when the images differ, like diff
+            if diff::diff(diff_args)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+    let outputs = [&args.output_file_name, &args.listing_file_name];
+    file::check_std(
+        args.file_paths.iter().map(|p| p.as_path()),
+        outputs
+            .into_iter()
+            .flatten()
+            .map(|p| p.as_path())
+            .chain(args.output.files()),
+    )?;
 
-    let (exprs, label_table) = parse::parse(text, args.base_address)?;
-    let bytes = gen::gen(exprs, label_table)?;
+    let mut sections_list = Vec::new();
+    let mut label_tables = Vec::new();
+    let mut texts = Vec::new();
+    for file_path in &args.file_paths {
+        let text = file::read(file_path)?;
+        let (mut sections, label_table) = parse::parse(text.clone())
+            .with_context(|| format!("could not assemble '{}'", file_path.display()))?;
+        parse::set_file(&mut sections, &file::source_name(file_path));
+        sections_list.push(sections);
+        label_tables.push(label_table);
+        texts.push(text);
+    }
+    if args.shared_scope {
+        link::share_labels(&mut label_tables);
+    }
+
+    if args.object {
+        if args.file_paths.len() > 1 {
+            return Err(anyhow!("-c takes a single file"));
+        }
+        let object = gen::gen_object(sections_list.remove(0), label_tables.remove(0))?;
+        let mut output: Vec<u8> = Vec::new();
+        object::write(&object, &mut output)?;
+        let output_file_name = args.output_file_name.unwrap_or_else(|| "a.o".into());
+        file::write(&output_file_name, &output)?;
+        return Ok(());
+    }
+
+    if args.listing_file_name.is_some() && args.file_paths.len() > 1 {
+        return Err(anyhow!("-l takes a single file"));
+    }
 
-    let mut output_file = std::fs::File::create(&args.output_file_name)
-        .with_context(|| "could not create file".to_string())?;
+    let layout = args.output.layout.layout()?;
+    let (mut image, sections, label_table) = if args.file_paths.len() == 1 {
+        let (mut sections, mut label_table) = (sections_list.remove(0), label_tables.remove(0));
+        layout::place(&layout, &mut sections, &mut label_table)?;
+        let image = gen::gen(sections.clone(), label_table.clone())?;
+        (image, sections, label_table)
+    } else {
+        let mut objects = Vec::new();
+        for ((sections, label_table), file_path) in sections_list
+            .into_iter()
+            .zip(label_tables)
+            .zip(&args.file_paths)
+        {
+            let object = gen::gen_object(sections, label_table)
+                .with_context(|| format!("could not assemble '{}'", file_path.display()))?;
+            objects.push(object);
+        }
+        link::link(objects, &layout)?
+    };
+    let name = file::module_name(&args.file_paths[0]);
+    output::write(
+        &mut image,
+        &sections,
+        &label_table,
+        &layout,
+        &name,
+        &args.output,
+        args.output_file_name.as_deref(),
+    )?;
 
-    for byte in bytes {
-        writeln!(output_file, "{:02x}", byte)?;
+    if let Some(listing_file_name) = &args.listing_file_name {
+        let lines: Vec<_> = sections.iter().flat_map(|s| s.lines.clone()).collect();
+        let mut output: Vec<u8> = Vec::new();
+        listing::write(&texts[0], &lines, &image, &label_table, &mut output)?;
+        file::write(listing_file_name, &output)?;
     }
 
     Ok(())
@@ -0,0 +1,276 @@
+use crate::parse::{Binding, Label, Section};
+use anyhow::{anyhow, Context, Result};
+use std::io::Write;
+
+#[derive(clap::ValueEnum, Debug, PartialEq, Clone, Copy)]
+pub enum MapFormat {
+    /// one symbol per line
+    Text,
+    /// an array of symbol objects
+    Json,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SymbolKind {
+    Label,
+    /// defined with `.equ`
+    Constant,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u16,
+    /// empty for constants
+    pub section: String,
+    /// bytes up to the next label in the section, 0 for constants
+    pub size: u16,
+    pub kind: SymbolKind,
+    pub binding: Binding,
+}
+
+/// Collects the labels and constants of `label_table`, sorted by address.
+/// `sections` must be placed so that label sizes can be measured.
+pub fn symbols(label_table: &[Label], sections: &[Section]) -> Vec<Symbol> {
+    let mut labels: Vec<&Label> = label_table
+        .iter()
+        .filter(|l| l.binding != Binding::Extern)
+        .collect();
+    labels.sort_by_key(|l| (l.address, l.name.clone()));
+
+    labels
+        .iter()
+        .map(|label| {
+            let size = match sections.iter().find(|s| s.name == label.section) {
+                Some(section) if !label.is_constant() => {
+                    let end = labels
+                        .iter()
+                        .filter(|l| l.section == label.section && l.address > label.address)
+                        .map(|l| l.address as u32)
+                        .min()
+                        .unwrap_or(section.address as u32 + section.size as u32);
+                    (end - label.address as u32) as u16
+                }
+                _ => 0,
+            };
+            Symbol {
+                name: label.name.clone(),
+                address: label.address,
+                section: label.section.clone(),
+                size,
+                kind: if label.is_constant() {
+                    SymbolKind::Constant
+                } else {
+                    SymbolKind::Label
+                },
+                binding: label.binding.clone(),
+            }
+        })
+        .collect()
+}
+
+pub fn write(symbols: &[Symbol], format: MapFormat, output: &mut impl Write) -> Result<()> {
+    match format {
+        MapFormat::Text => write_text(symbols, output),
+        MapFormat::Json => write_json(symbols, output),
+    }
+}
+
+fn kind(symbol: &Symbol) -> &'static str {
+    match symbol.kind {
+        SymbolKind::Label => "label",
+        SymbolKind::Constant => "constant",
+    }
+}
+
+fn binding(symbol: &Symbol) -> &'static str {
+    match symbol.binding {
+        Binding::Global => "global",
+        _ => "local",
+    }
+}
+
+pub fn write_text(symbols: &[Symbol], output: &mut impl Write) -> Result<()> {
+    writeln!(
+        output,
+        "{:<9}{:<10}{:<6}{:<10}{:<8}name",
+        "address", "section", "size", "kind", "binding"
+    )?;
+    for symbol in symbols {
+        let section = if symbol.section.is_empty() {
+            "-"
+        } else {
+            symbol.section.as_str()
+        };
+        writeln!(
+            output,
+            "{:04x}     {:<10}{:04x}  {:<10}{:<8}{}",
+            symbol.address,
+            section,
+            symbol.size,
+            kind(symbol),
+            binding(symbol),
+            symbol.name
+        )?;
+    }
+    Ok(())
+}
+
+pub fn write_json(symbols: &[Symbol], output: &mut impl Write) -> Result<()> {
+    writeln!(output, "[")?;
+    for (i, symbol) in symbols.iter().enumerate() {
+        let end = if i + 1 == symbols.len() { "" } else { "," };
+        writeln!(
+            output,
+            "  {{\"name\": \"{}\", \"address\": {}, \"section\": \"{}\", \"size\": {}, \"kind\": \"{}\", \"binding\": \"{}\"}}{}",
+            escape(&symbol.name),
+            symbol.address,
+            escape(&symbol.section),
+            symbol.size,
+            kind(symbol),
+            binding(symbol),
+            end
+        )?;
+    }
+    writeln!(output, "]")?;
+    Ok(())
+}
+
+/// Reads a map written by `write`, in either format.
+pub fn read(text: &str) -> Result<Vec<Symbol>> {
+    let json = text.trim_start().starts_with('[');
+    let mut symbols = Vec::new();
+    for (line_num, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let symbol = if json {
+            if !line.starts_with('{') {
+                continue;
+            }
+            read_json_symbol(line)
+        } else {
+            if line.is_empty() || line_num == 0 {
+                continue;
+            }
+            read_text_symbol(line)
+        };
+        symbols
+            .push(symbol.with_context(|| format!("invalid map : line {}:{}", line_num + 1, line))?);
+    }
+    Ok(symbols)
+}
+
+fn symbol_kind(kind: &str) -> Result<SymbolKind> {
+    match kind {
+        "label" => Ok(SymbolKind::Label),
+        "constant" => Ok(SymbolKind::Constant),
+        _ => Err(anyhow!("unknown kind {}", kind)),
+    }
+}
+
+fn symbol_binding(binding: &str) -> Result<Binding> {
+    match binding {
+        "local" => Ok(Binding::Local),
+        "global" => Ok(Binding::Global),
+        _ => Err(anyhow!("unknown binding {}", binding)),
+    }
+}
+
+fn read_text_symbol(line: &str) -> Result<Symbol> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [address, section, size, kind, binding, name] = fields.as_slice() else {
+        return Err(anyhow!("expected 6 fields"));
+    };
+    Ok(Symbol {
+        name: name.to_string(),
+        address: u16::from_str_radix(address, 16)?,
+        section: if *section == "-" { "" } else { section }.to_string(),
+        size: u16::from_str_radix(size, 16)?,
+        kind: symbol_kind(kind)?,
+        binding: symbol_binding(binding)?,
+    })
+}
+
+fn read_json_symbol(line: &str) -> Result<Symbol> {
+    // only the flat objects written by `write_json` are understood
+    let field = |key: &str| -> Result<String> {
+        let start = line
+            .find(&format!("\"{}\": ", key))
+            .ok_or_else(|| anyhow!("missing {}", key))?
+            + key.len()
+            + 4;
+        let rest = &line[start..];
+        match rest.strip_prefix('"') {
+            Some(rest) => {
+                let mut value = String::new();
+                let mut chars = rest.chars();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => return Ok(value),
+                        '\\' => value.extend(chars.next()),
+                        _ => value.push(c),
+                    }
+                }
+                Err(anyhow!("unterminated {}", key))
+            }
+            None => Ok(rest
+                .split([',', '}'])
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string()),
+        }
+    };
+    Ok(Symbol {
+        name: field("name")?,
+        address: field("address")?.parse()?,
+        section: field("section")?,
+        size: field("size")?.parse()?,
+        kind: symbol_kind(&field("kind")?)?,
+        binding: symbol_binding(&field("binding")?)?,
+    })
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::layout::{place, Layout};
+    use crate::parse::parse;
+
+    #[test]
+    fn can_write_map() -> Result<()> {
+        let text = ".equ SIZE, 0x10\n.global start\nstart:\n\tlil x1, SIZE@l\nloop:\n\tjal zero, loop\n.bss\nbuf:\n\t.space 0x10\n";
+        let (mut sections, mut label_table) = parse(text.to_string())?;
+        place(&Layout::new(0x100), &mut sections, &mut label_table)?;
+        let symbols = symbols(&label_table, &sections);
+
+        let mut output: Vec<u8> = Vec::new();
+        write_text(&symbols, &mut output)?;
+        let expect = "\
+address  section   size  kind      binding name
+0010     -         0000  constant  local   SIZE
+0100     .text     0002  label     global  start
+0102     .text     0002  label     local   loop
+0104     .bss      0010  label     local   buf
+";
+        assert_eq!(std::str::from_utf8(&output)?, expect);
+
+        let mut output: Vec<u8> = Vec::new();
+        write_json(&symbols[..2], &mut output)?;
+        let expect = r#"[
+  {"name": "SIZE", "address": 16, "section": "", "size": 0, "kind": "constant", "binding": "local"},
+  {"name": "start", "address": 256, "section": ".text", "size": 2, "kind": "label", "binding": "global"}
+]
+"#;
+        assert_eq!(std::str::from_utf8(&output)?, expect);
+        assert_eq!(read(expect)?, symbols[..2]);
+
+        let mut output: Vec<u8> = Vec::new();
+        write_text(&symbols, &mut output)?;
+        assert_eq!(read(std::str::from_utf8(&output)?)?, symbols);
+        Ok(())
+    }
+}
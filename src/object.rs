@@ -0,0 +1,378 @@
+use crate::parse::{Binding, Label, Line, LineKind, SectionKind};
+use anyhow::{anyhow, Context, Result};
+use std::io::Write;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum RelocKind {
+    /// 8 bit PC relative offset of `jal`
+    Jal,
+    /// 5 bit PC relative offset of I5 branches
+    Branch,
+    /// low 8 bits of an address (`@l`)
+    Low,
+    /// high 8 bits of an address (`@h`)
+    High,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Relocation {
+    pub section: String,
+    pub offset: u16,
+    pub kind: RelocKind,
+    pub symbol: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ObjectSection {
+    pub name: String,
+    pub kind: SectionKind,
+    pub size: u16,
+    pub bytes: Vec<u8>,
+    pub lines: Vec<Line>,
+}
+
+/// A relocatable object. Symbol and relocation addresses are relative to the
+/// start of their section.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Object {
+    pub sections: Vec<ObjectSection>,
+    pub symbols: Vec<Label>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// Patches the immediate field of the instruction `word` at `address` so that
+/// it refers to `target`.
+pub fn relocate(kind: &RelocKind, word: u16, target: u16, address: u16) -> Result<u16> {
+    let offset = target as i32 - address as i32;
+    match kind {
+        RelocKind::Jal => {
+            if !(-128..=127).contains(&offset) {
+                return Err(anyhow!("offset {} is out of range", offset));
+            }
+            Ok(word & 0x00FF | (offset as u16 & 0x00FF) << 8)
+        }
+        RelocKind::Branch => {
+            if !(-16..=15).contains(&offset) {
+                return Err(anyhow!("offset {} is out of range", offset));
+            }
+            Ok(word & 0x07FF | (offset as u16 & 0x001F) << 11)
+        }
+        RelocKind::Low => Ok(word & 0x00FF | (target & 0x00FF) << 8),
+        RelocKind::High => Ok(word & 0x00FF | (target & 0xFF00)),
+    }
+}
+
+pub fn write(object: &Object, output: &mut impl Write) -> Result<()> {
+    writeln!(output, "zktc-obj")?;
+    for section in &object.sections {
+        let kind = match section.kind {
+            SectionKind::Progbits => "progbits",
+            SectionKind::Nobits => "nobits",
+        };
+        writeln!(
+            output,
+            "section {} {} 0x{:04x}",
+            section.name, kind, section.size
+        )?;
+        for chunk in section.bytes.chunks(16) {
+            let chunk: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            writeln!(output, "bytes {}", chunk.join(" "))?;
+        }
+        let mut file = "";
+        for line in &section.lines {
+            if line.file != file {
+                file = &line.file;
+                writeln!(output, "file {}", file)?;
+            }
+            let kind = match line.kind {
+                LineKind::Inst => "inst",
+                LineKind::Data => "data",
+                LineKind::Space => "space",
+            };
+            writeln!(
+                output,
+                "line 0x{:04x} 0x{:04x} {} {} {} {}",
+                line.address, line.size, kind, line.line_num, line.column, line.text
+            )?;
+        }
+    }
+    for symbol in &object.symbols {
+        let binding = match symbol.binding {
+            Binding::Local => "local",
+            Binding::Global => "global",
+            Binding::Extern => "extern",
+        };
+        let section = if symbol.section.is_empty() {
+            "-"
+        } else {
+            symbol.section.as_str()
+        };
+        writeln!(
+            output,
+            "symbol {} {} 0x{:04x} {}",
+            symbol.name, section, symbol.address, binding
+        )?;
+    }
+    for relocation in &object.relocations {
+        let kind = match relocation.kind {
+            RelocKind::Jal => "jal",
+            RelocKind::Branch => "branch",
+            RelocKind::Low => "low",
+            RelocKind::High => "high",
+        };
+        writeln!(
+            output,
+            "reloc {} 0x{:04x} {} {}",
+            relocation.section, relocation.offset, kind, relocation.symbol
+        )?;
+    }
+    Ok(())
+}
+
+pub fn read(text: &str) -> Result<Object> {
+    let mut object = Object::default();
+    let mut lines = text.lines().enumerate();
+
+    match lines.next() {
+        Some((_, "zktc-obj")) => {}
+        _ => return Err(anyhow!("not a zktc object file")),
+    }
+
+    // source file of the following line records
+    let mut file = String::new();
+    for (line_num, line) in lines {
+        read_line(&mut object, &mut file, line)
+            .with_context(|| format!("invalid object : line {}:{}", line_num + 1, line))?;
+    }
+    check(&object).context("invalid object")?;
+    Ok(object)
+}
+
+/// Checks that the bytes of each section match its size, and that line and
+/// relocation records fall inside their section.
+fn check(object: &Object) -> Result<()> {
+    for section in &object.sections {
+        let size = match section.kind {
+            SectionKind::Progbits => section.size as usize,
+            SectionKind::Nobits => 0,
+        };
+        if section.bytes.len() != size {
+            return Err(anyhow!(
+                "section {} has {} bytes, expected {}",
+                section.name,
+                section.bytes.len(),
+                size
+            ));
+        }
+        for line in &section.lines {
+            if line.address as u32 + line.size as u32 > section.size as u32 {
+                return Err(anyhow!(
+                    "line at 0x{:04x} is outside of section {}",
+                    line.address,
+                    section.name
+                ));
+            }
+        }
+    }
+    for relocation in &object.relocations {
+        let section = object
+            .sections
+            .iter()
+            .find(|s| s.name == relocation.section)
+            .ok_or_else(|| anyhow!("relocation in unknown section {}", relocation.section))?;
+        if section.kind != SectionKind::Progbits
+            || relocation.offset as u32 + 2 > section.size as u32
+        {
+            return Err(anyhow!(
+                "relocation at 0x{:04x} is outside of section {}",
+                relocation.offset,
+                relocation.section
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn read_line(object: &mut Object, file: &mut String, line: &str) -> Result<()> {
+    // file names and the source text of a line record may contain spaces
+    if let Some(name) = line.strip_prefix("file ") {
+        *file = name.to_string();
+        return Ok(());
+    }
+    if let Some(record) = line.strip_prefix("line ") {
+        let fields: Vec<&str> = record.splitn(6, ' ').collect();
+        let section = object
+            .sections
+            .last_mut()
+            .ok_or_else(|| anyhow!("line outside of section"))?;
+        if let [address, size, kind, line_num, column, text] = fields.as_slice() {
+            let kind = match *kind {
+                "inst" => LineKind::Inst,
+                "data" => LineKind::Data,
+                "space" => LineKind::Space,
+                _ => return Err(anyhow!("unknown line kind {}", kind)),
+            };
+            section.lines.push(Line {
+                address: read_hex(address)?,
+                size: read_hex(size)?,
+                kind,
+                file: file.clone(),
+                line_num: line_num.parse()?,
+                column: column.parse()?,
+                text: text.to_string(),
+            });
+            return Ok(());
+        }
+        return Err(anyhow!("unknown record"));
+    }
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.as_slice() {
+        [] => {}
+        ["section", name, kind, size] => {
+            let kind = match *kind {
+                "progbits" => SectionKind::Progbits,
+                "nobits" => SectionKind::Nobits,
+                _ => return Err(anyhow!("unknown section kind {}", kind)),
+            };
+            object.sections.push(ObjectSection {
+                name: name.to_string(),
+                kind,
+                size: read_hex(size)?,
+                bytes: Vec::new(),
+                lines: Vec::new(),
+            });
+        }
+        ["bytes", bytes @ ..] => {
+            let section = object
+                .sections
+                .last_mut()
+                .ok_or_else(|| anyhow!("bytes outside of section"))?;
+            for byte in bytes {
+                section.bytes.push(u8::from_str_radix(byte, 16)?);
+            }
+        }
+        ["symbol", name, section, address, binding] => {
+            let binding = match *binding {
+                "local" => Binding::Local,
+                "global" => Binding::Global,
+                "extern" => Binding::Extern,
+                _ => return Err(anyhow!("unknown binding {}", binding)),
+            };
+            let section = if *section == "-" { "" } else { section };
+            object.symbols.push(Label {
+                name: name.to_string(),
+                section: section.to_string(),
+                address: read_hex(address)?,
+                binding,
+            });
+        }
+        ["reloc", section, offset, kind, symbol] => {
+            let kind = match *kind {
+                "jal" => RelocKind::Jal,
+                "branch" => RelocKind::Branch,
+                "low" => RelocKind::Low,
+                "high" => RelocKind::High,
+                _ => return Err(anyhow!("unknown relocation {}", kind)),
+            };
+            object.relocations.push(Relocation {
+                section: section.to_string(),
+                offset: read_hex(offset)?,
+                kind,
+                symbol: symbol.to_string(),
+            });
+        }
+        _ => return Err(anyhow!("unknown record")),
+    }
+    Ok(())
+}
+
+fn read_hex(s: &str) -> Result<u16> {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16)
+        .with_context(|| format!("could not parse {}", s))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn can_relocate() -> Result<()> {
+        assert_eq!(relocate(&RelocKind::Jal, 0x0010, 0x0000, 0x0004)?, 0xfc10);
+        assert_eq!(
+            relocate(&RelocKind::Branch, 0x0043, 0x0008, 0x0004)?,
+            0x2043
+        );
+        assert_eq!(relocate(&RelocKind::Low, 0x0031, 0x8012, 0x0000)?, 0x1231);
+        assert_eq!(relocate(&RelocKind::High, 0x0032, 0x8012, 0x0000)?, 0x8032);
+        assert!(relocate(&RelocKind::Branch, 0x0043, 0x0020, 0x0000).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_write_and_read_object() -> Result<()> {
+        let object = Object {
+            sections: vec![
+                ObjectSection {
+                    name: ".text".to_string(),
+                    kind: SectionKind::Progbits,
+                    size: 4,
+                    bytes: vec![0x31, 0x00, 0x10, 0x00],
+                    lines: vec![Line {
+                        address: 0,
+                        size: 2,
+                        kind: LineKind::Inst,
+                        file: "src/main file.asm".to_string(),
+                        line_num: 3,
+                        column: 2,
+                        text: "lil x1, 0x00@l // low byte".to_string(),
+                    }],
+                },
+                ObjectSection {
+                    name: ".bss".to_string(),
+                    kind: SectionKind::Nobits,
+                    size: 16,
+                    bytes: vec![],
+                    lines: vec![],
+                },
+            ],
+            symbols: vec![
+                Label {
+                    name: "start".to_string(),
+                    section: ".text".to_string(),
+                    address: 0,
+                    binding: Binding::Global,
+                },
+                Label {
+                    name: "putc".to_string(),
+                    section: "".to_string(),
+                    address: 0,
+                    binding: Binding::Extern,
+                },
+            ],
+            relocations: vec![Relocation {
+                section: ".text".to_string(),
+                offset: 2,
+                kind: RelocKind::Jal,
+                symbol: "putc".to_string(),
+            }],
+        };
+        let mut text: Vec<u8> = Vec::new();
+        write(&object, &mut text)?;
+
+        assert_eq!(read(std::str::from_utf8(&text)?)?, object);
+        assert!(read("section .text progbits 0x0000").is_err());
+
+        // records that don't fit their section are rejected
+        let text = std::str::from_utf8(&text)?;
+        for (from, to) in [
+            ("progbits 0x0004", "progbits 0x0002"),
+            ("line 0x0000 0x0002", "line 0x0004 0x0002"),
+            ("reloc .text 0x0002", "reloc .text 0xffff"),
+            ("reloc .text", "reloc .data"),
+        ] {
+            assert!(read(&text.replace(from, to)).is_err(), "{}", to);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+use crate::boot::{self, BootArgs};
+use crate::debug;
+use crate::file;
+use crate::format::{self, FormatArgs};
+use crate::header::HeaderArgs;
+use crate::image::Image;
+use crate::layout::{self, Layout, LayoutArgs};
+use crate::map::{self, MapFormat};
+use crate::parse::{Label, Line, Section, SectionKind};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// Placement of the sections and the files written from the placed image,
+/// shared by the assembler and the linker.
+#[derive(clap::Args)]
+pub struct OutputArgs {
+    /// write a line table mapping addresses to file:line:column
+    #[arg(short = 'g')]
+    pub debug_file_name: Option<PathBuf>,
+
+    /// write a map of the labels and constants
+    #[arg(short = 'm')]
+    pub map_file_name: Option<PathBuf>,
+
+    /// format of the map file
+    #[arg(long = "map-format", value_enum, default_value_t = MapFormat::Text)]
+    pub map_format: MapFormat,
+
+    #[command(flatten)]
+    pub layout: LayoutArgs,
+
+    #[command(flatten)]
+    pub format: FormatArgs,
+
+    #[command(flatten)]
+    pub header: HeaderArgs,
+
+    #[command(flatten)]
+    pub boot: BootArgs,
+}
+
+impl OutputArgs {
+    /// The files written besides the image.
+    pub fn files(&self) -> Vec<&Path> {
+        [
+            &self.debug_file_name,
+            &self.map_file_name,
+            &self.header.c_header,
+            &self.header.rust_module,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|p| p.as_path())
+        .collect()
+    }
+}
+
+/// Writes the boot header into the placed image and checks its size limits,
+/// then writes the image to `path` (`a.mem`, `a.bin`, ... by default) and the
+/// other files asked for. `name` is the module name of formats and headers
+/// that carry one.
+pub fn write(
+    image: &mut Image,
+    sections: &[Section],
+    label_table: &[Label],
+    layout: &Layout,
+    name: &str,
+    args: &OutputArgs,
+    path: Option<&Path>,
+) -> Result<()> {
+    let format_args = FormatArgs {
+        start_address: args.format.start_address.or_else(|| start(sections)),
+        ..args.format.clone()
+    };
+    let lines: Vec<Line> = sections.iter().flat_map(|s| s.lines.clone()).collect();
+    boot::apply(
+        image,
+        label_table,
+        &lines,
+        args.format.entry.as_deref(),
+        args.format.gap_fill.unwrap_or(0),
+        &args.boot,
+    )?;
+    layout::check_limits(&args.layout.section_size, sections, label_table)?;
+    format::check_size(image, label_table, &format_args)?;
+    if args.layout.print_memory_usage {
+        layout::write_usage(layout, sections, &mut std::io::stderr())?;
+    }
+
+    if let Some(debug_file_name) = &args.debug_file_name {
+        let mut output: Vec<u8> = Vec::new();
+        debug::write(&debug::LineTable::new(&lines), &mut output)?;
+        file::write(debug_file_name, &output)?;
+    }
+
+    if let Some(map_file_name) = &args.map_file_name {
+        let mut output: Vec<u8> = Vec::new();
+        let symbols = map::symbols(label_table, sections);
+        map::write(&symbols, args.map_format, &mut output)?;
+        file::write(map_file_name, &output)?;
+    }
+
+    args.header.write(image, label_table, name)?;
+
+    let default = PathBuf::from(format!("a.{}", args.format.format.extension()));
+    format::write_file(
+        image,
+        label_table,
+        &lines,
+        name,
+        &format_args,
+        path.unwrap_or(&default),
+    )
+}
+
+/// Lowest address of the sections that hold bytes. The image starts there
+/// rather than at its first byte, so a leading `.org` is kept as a gap.
+fn start(sections: &[Section]) -> Option<u16> {
+    sections
+        .iter()
+        .filter(|s| s.kind == SectionKind::Progbits && s.size > 0)
+        .map(|s| s.address)
+        .min()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::format::Format;
+    use crate::gen::gen;
+    use crate::layout::place;
+    use crate::parse::parse;
+
+    #[test]
+    fn can_start_at_first_section() -> Result<()> {
+        let (mut sections, mut label_table) = parse("\t.org 0x4\n\ttrap\n".to_string())?;
+        place(&Layout::new(0), &mut sections, &mut label_table)?;
+        let image = gen(sections.clone(), label_table.clone())?;
+        assert_eq!(start(&sections), Some(0x0000));
+
+        let args = FormatArgs {
+            start_address: start(&sections),
+            ..FormatArgs::new(Format::Mem)
+        };
+        let mut output: Vec<u8> = Vec::new();
+        format::write(&image, &label_table, &[], "a", &args, &mut output)?;
+        assert_eq!(std::str::from_utf8(&output)?, "00\n00\n00\n00\nff\nff\n");
+        Ok(())
+    }
+}
@@ -3,14 +3,14 @@ use nom::{
     branch::alt,
     bytes::complete::{tag, take_till, take_while1},
     character::{
-        complete::{alpha1, alphanumeric1, hex_digit1, multispace0},
+        complete::{alpha1, alphanumeric1, digit1, hex_digit1, multispace0},
         is_alphanumeric,
     },
     error::ErrorKind,
     IResult,
 };
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Expr {
     Inst {
         inst_type: InstType,
@@ -30,9 +30,39 @@ pub enum Expr {
         name: String,
         address: u16,
     },
+    Section {
+        name: String,
+    },
+    Space {
+        size: u16,
+        address: u16,
+    },
+    Symbol {
+        name: String,
+        binding: Binding,
+    },
+    Org {
+        address: u16,
+    },
+    Equ {
+        name: String,
+        value: u16,
+    },
 }
 
-#[derive(Debug, PartialEq)]
+impl Expr {
+    /// Returns the address of instructions and data.
+    pub fn address(&self) -> Option<u16> {
+        match self {
+            Expr::Inst { address, .. }
+            | Expr::Const { address, .. }
+            | Expr::Space { address, .. } => Some(*address),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum InstType {
     R,
     I5,
@@ -48,16 +78,94 @@ pub enum ConstType {
     Byte,
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub enum SectionKind {
+    Progbits,
+    Nobits,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Section {
+    pub name: String,
+    pub kind: SectionKind,
+    pub address: u16,
+    pub size: u16,
+    pub exprs: Vec<Expr>,
+    pub lines: Vec<Line>,
+}
+
+/// What a source line emits.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LineKind {
+    Inst,
+    Data,
+    /// bytes reserved with `.space`
+    Space,
+}
+
+/// Source line of the instruction or data occupying `size` bytes at `address`.
+/// `file` is empty until the caller names the source with `set_file`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Line {
+    pub address: u16,
+    pub size: u16,
+    pub kind: LineKind,
+    pub file: String,
+    pub line_num: usize,
+    pub column: usize,
+    pub text: String,
+}
+
+impl Label {
+    pub fn is_constant(&self) -> bool {
+        self.section.is_empty() && self.binding != Binding::Extern
+    }
+}
+
+impl Section {
+    pub fn new(name: &str) -> Self {
+        let kind = if name == ".bss" || name.starts_with(".bss.") {
+            SectionKind::Nobits
+        } else {
+            SectionKind::Progbits
+        };
+        Section {
+            name: name.to_string(),
+            kind,
+            address: 0,
+            size: 0,
+            exprs: Vec::new(),
+            lines: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Binding {
+    Local,
+    Global,
+    /// defined in another object, `section` is empty
+    Extern,
+}
+
+/// A label, or a `.equ` constant when `section` is empty and the binding is
+/// not `Extern`.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Label {
     pub name: String,
+    pub section: String,
     pub address: u16,
+    pub binding: Binding,
 }
 
-pub fn parse(text: String, base_address: u16) -> Result<(Vec<Expr>, Vec<Label>)> {
-    let mut exprs: Vec<Expr> = Vec::new();
+/// Parses `text` into sections whose expressions and labels are addressed
+/// relative to the start of their section. Call `layout::place` to assign
+/// absolute addresses before generating code.
+pub fn parse(text: String) -> Result<(Vec<Section>, Vec<Label>)> {
+    let mut sections: Vec<Section> = vec![Section::new(".text")];
     let mut label_table: Vec<Label> = Vec::new();
-    let mut address: u16 = base_address;
+    let mut symbols: Vec<(String, Binding)> = Vec::new();
+    let mut current: usize = 0;
 
     for (line_num, line) in text.lines().enumerate() {
         // delete empty line and comment
@@ -65,56 +173,157 @@ pub fn parse(text: String, base_address: u16) -> Result<(Vec<Expr>, Vec<Label>)>
             continue;
         }
         match parse_line(line) {
-            Ok((_, expr)) => match expr {
-                Expr::Label { name, .. } => {
-                    label_table.push(Label { name, address });
-                }
-                Expr::Inst {
-                    inst_type,
-                    mnemonic,
-                    rd,
-                    rs,
-                    imm,
-                    symbol,
-                    ..
-                } => {
-                    exprs.push(Expr::Inst {
+            Ok((_, expr)) => {
+                let section = &mut sections[current];
+                let address = section.size;
+                let kind = match expr {
+                    Expr::Inst { .. } => Some(LineKind::Inst),
+                    Expr::Const { .. } => Some(LineKind::Data),
+                    Expr::Space { .. } => Some(LineKind::Space),
+                    _ => None,
+                };
+                // end of `size` bytes emitted at the location counter
+                let end = |section: &Section, size: u16| {
+                    address.checked_add(size).ok_or_else(|| {
+                        anyhow!(
+                            "section {} overflows : line {}:{}",
+                            section.name,
+                            line_num + 1,
+                            line
+                        )
+                    })
+                };
+                match expr {
+                    Expr::Label { name, .. } => {
+                        label_table.push(Label {
+                            name,
+                            section: section.name.clone(),
+                            address,
+                            binding: Binding::Local,
+                        });
+                    }
+                    Expr::Symbol { name, binding } => {
+                        symbols.push((name, binding));
+                    }
+                    Expr::Equ { name, value } => {
+                        label_table.push(Label {
+                            name,
+                            section: "".to_string(),
+                            address: value,
+                            binding: Binding::Local,
+                        });
+                    }
+                    Expr::Org { address: offset } => {
+                        if offset < address {
+                            return Err(anyhow!(
+                                "cannot move location counter backwards : line {}:{}",
+                                line_num + 1,
+                                line
+                            ));
+                        }
+                        section.size = offset;
+                    }
+                    Expr::Section { name } => {
+                        current = match sections.iter().position(|s| s.name == name) {
+                            Some(index) => index,
+                            None => {
+                                sections.push(Section::new(&name));
+                                sections.len() - 1
+                            }
+                        };
+                    }
+                    Expr::Space { size, .. } => {
+                        section.exprs.push(Expr::Space { size, address });
+                        section.size = end(section, size)?;
+                    }
+                    _ if section.kind == SectionKind::Nobits => {
+                        return Err(anyhow!(
+                            "initialized data in nobits section {} : line {}:{}",
+                            section.name,
+                            line_num + 1,
+                            line
+                        ));
+                    }
+                    Expr::Inst {
                         inst_type,
                         mnemonic,
                         rd,
                         rs,
                         imm,
                         symbol,
-                        address,
-                    });
-                    address += 2;
+                        ..
+                    } => {
+                        section.exprs.push(Expr::Inst {
+                            inst_type,
+                            mnemonic,
+                            rd,
+                            rs,
+                            imm,
+                            symbol,
+                            address,
+                        });
+                        section.size = end(section, 2)?;
+                    }
+                    Expr::Const {
+                        val, const_type, ..
+                    } => {
+                        section.exprs.push(Expr::Const {
+                            val,
+                            const_type: const_type.clone(),
+                            address,
+                        });
+                        let size = if const_type == ConstType::Byte { 1 } else { 2 };
+                        section.size = end(section, size)?;
+                    }
                 }
-                Expr::Const {
-                    val, const_type, ..
-                } => {
-                    exprs.push(Expr::Const {
-                        val,
-                        const_type: const_type.clone(),
+                if let Some(kind) = kind {
+                    let section = &mut sections[current];
+                    section.lines.push(Line {
                         address,
+                        size: section.size - address,
+                        kind,
+                        file: String::new(),
+                        line_num: line_num + 1,
+                        column: line.len() - line.trim_start().len() + 1,
+                        text: line.trim().to_string(),
                     });
-                    if const_type == ConstType::Byte {
-                        address += 1;
-                    } else {
-                        address += 2;
-                    }
                 }
-            },
+            }
             Err(_) => {
                 return Err(anyhow!("Syntax Error : line {}:{}", line_num + 1, line));
             }
         }
     }
-    Ok((exprs, label_table))
+
+    for (name, binding) in symbols {
+        let label = label_table.iter_mut().find(|l| l.name == name);
+        match (binding, label) {
+            (Binding::Global, Some(label)) => label.binding = Binding::Global,
+            (Binding::Global, None) => {
+                return Err(anyhow!("global symbol {} is not defined", name));
+            }
+            (_, Some(_)) => {}
+            (_, None) => label_table.push(Label {
+                name,
+                section: "".to_string(),
+                address: 0,
+                binding: Binding::Extern,
+            }),
+        }
+    }
+    Ok((sections, label_table))
+}
+
+/// Sets the source file of every line of `sections`.
+pub fn set_file(sections: &mut [Section], file: &str) {
+    for line in sections.iter_mut().flat_map(|s| s.lines.iter_mut()) {
+        line.file = file.to_string();
+    }
 }
 
 fn parse_line(line: &str) -> IResult<&str, Expr> {
     let (line, _) = multispace0(line)?;
-    let result = alt((parse_inst, parse_word, parse_label))(line)?;
+    let result = alt((parse_inst, parse_directive, parse_label))(line)?;
     Ok(result)
 }
 
@@ -271,7 +480,7 @@ fn parse_inst(line: &str) -> IResult<&str, Expr> {
     }
 }
 
-fn parse_word(line: &str) -> IResult<&str, Expr> {
+fn parse_directive(line: &str) -> IResult<&str, Expr> {
     let (line, _) = multispace0(line)?;
     let (line, _) = tag(".")(line)?;
     let (line, directive) = alpha1(line)?;
@@ -302,6 +511,86 @@ fn parse_word(line: &str) -> IResult<&str, Expr> {
                 },
             ))
         }
+        "space" => {
+            let (line, _) = multispace0(line)?;
+            let (line, size) = number(line)?;
+            if size > 0xFFFF {
+                let err = nom::error::Error::new(line, ErrorKind::Fail);
+                return Err(nom::Err::Error(err));
+            }
+            Ok((
+                line,
+                Expr::Space {
+                    size: size as u16,
+                    address: 0,
+                },
+            ))
+        }
+        "org" => {
+            let (line, _) = multispace0(line)?;
+            let (line, address) = number(line)?;
+            if address > 0xFFFF {
+                let err = nom::error::Error::new(line, ErrorKind::Fail);
+                return Err(nom::Err::Error(err));
+            }
+            Ok((
+                line,
+                Expr::Org {
+                    address: address as u16,
+                },
+            ))
+        }
+        "section" => {
+            let (line, _) = multispace0(line)?;
+            let (line, name) = take_while1(is_section_name)(line)?;
+            Ok((
+                line,
+                Expr::Section {
+                    name: name.to_string(),
+                },
+            ))
+        }
+        "global" | "extern" => {
+            let (line, _) = multispace0(line)?;
+            let (line, name) = take_while1(is_ident)(line)?;
+            let binding = if directive == "global" {
+                Binding::Global
+            } else {
+                Binding::Extern
+            };
+            Ok((
+                line,
+                Expr::Symbol {
+                    name: name.to_string(),
+                    binding,
+                },
+            ))
+        }
+        "equ" => {
+            let (line, _) = multispace0(line)?;
+            let (line, name) = take_while1(is_ident)(line)?;
+            let (line, _) = multispace0(line)?;
+            let (line, _) = tag(",")(line)?;
+            let (line, _) = multispace0(line)?;
+            let (line, value) = number(line)?;
+            if value > 0xFFFF {
+                let err = nom::error::Error::new(line, ErrorKind::Fail);
+                return Err(nom::Err::Error(err));
+            }
+            Ok((
+                line,
+                Expr::Equ {
+                    name: name.to_string(),
+                    value: value as u16,
+                },
+            ))
+        }
+        "text" | "data" | "bss" => Ok((
+            line,
+            Expr::Section {
+                name: format!(".{}", directive),
+            },
+        )),
         _ => {
             let err = nom::error::Error::new(line, ErrorKind::Fail);
             Err(nom::Err::Error(err))
@@ -325,6 +614,27 @@ pub fn is_ident(ch: char) -> bool {
     is_alphanumeric(ch as u8) || ch == '_' || ch == '-'
 }
 
+pub fn is_section_name(ch: char) -> bool {
+    is_ident(ch) || ch == '.'
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal number.
+pub fn number(s: &str) -> IResult<&str, u32> {
+    if let Ok((s, _)) = tag::<&str, &str, nom::error::Error<&str>>("0x")(s) {
+        let (s, hex) = hex_digit1(s)?;
+        match u32::from_str_radix(hex, 16) {
+            Ok(n) => Ok((s, n)),
+            Err(_) => Err(nom::Err::Error(nom::error::Error::new(s, ErrorKind::Fail))),
+        }
+    } else {
+        let (s, dec) = digit1(s)?;
+        match dec.parse::<u32>() {
+            Ok(n) => Ok((s, n)),
+            Err(_) => Err(nom::Err::Error(nom::error::Error::new(s, ErrorKind::Fail))),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
 
@@ -335,15 +645,20 @@ mod test {
     fn can_parse_r_inst() -> Result<()> {
         let text = load_test_asm("test/asm/r_inst_test.asm");
 
-        let (result_exprs, result_label_table) = parse(text, 0)?;
+        let (result_sections, result_label_table) = parse(text)?;
+        let result_exprs = &result_sections[0].exprs;
         let expect_label_table: Vec<Label> = vec![
             Label {
                 name: "start".to_string(),
+                section: ".text".to_string(),
                 address: 0,
+                binding: Binding::Local,
             },
             Label {
                 name: "end".to_string(),
+                section: ".text".to_string(),
                 address: 18,
+                binding: Binding::Local,
             },
         ];
         let expect_exprs: Vec<Expr> = vec![
@@ -430,7 +745,7 @@ mod test {
             },
         ];
         assert_eq!(result_label_table, expect_label_table);
-        assert_eq!(result_exprs, expect_exprs);
+        assert_eq!(result_exprs, &expect_exprs);
 
         Ok(())
     }
@@ -439,15 +754,20 @@ mod test {
     fn can_parse_i5_inst() -> Result<()> {
         let text = load_test_asm("test/asm/i5_inst_test.asm");
 
-        let (result_exprs, result_label_table) = parse(text, 0)?;
+        let (result_sections, result_label_table) = parse(text)?;
+        let result_exprs = &result_sections[0].exprs;
         let expect_label_table: Vec<Label> = vec![
             Label {
                 name: "start".to_string(),
+                section: ".text".to_string(),
                 address: 0,
+                binding: Binding::Local,
             },
             Label {
                 name: "end".to_string(),
+                section: ".text".to_string(),
                 address: 28,
+                binding: Binding::Local,
             },
         ];
         let expect_exprs: Vec<Expr> = vec![
@@ -579,7 +899,7 @@ mod test {
             },
         ];
         assert_eq!(result_label_table, expect_label_table);
-        assert_eq!(result_exprs, expect_exprs);
+        assert_eq!(result_exprs, &expect_exprs);
 
         Ok(())
     }
@@ -588,15 +908,20 @@ mod test {
     fn can_parse_i8_inst() -> Result<()> {
         let text = load_test_asm("test/asm/i8_inst_test.asm");
 
-        let (result_exprs, result_label_table) = parse(text, 0)?;
+        let (result_sections, result_label_table) = parse(text)?;
+        let result_exprs = &result_sections[0].exprs;
         let expect_label_table: Vec<Label> = vec![
             Label {
                 name: "start".to_string(),
+                section: ".text".to_string(),
                 address: 0,
+                binding: Binding::Local,
             },
             Label {
                 name: "end".to_string(),
+                section: ".text".to_string(),
                 address: 6,
+                binding: Binding::Local,
             },
         ];
         let expect_exprs: Vec<Expr> = vec![
@@ -629,7 +954,7 @@ mod test {
             },
         ];
         assert_eq!(result_label_table, expect_label_table);
-        assert_eq!(result_exprs, expect_exprs);
+        assert_eq!(result_exprs, &expect_exprs);
 
         Ok(())
     }
@@ -638,15 +963,20 @@ mod test {
     fn can_parse_c1_inst() -> Result<()> {
         let text = load_test_asm("test/asm/c1_inst_test.asm");
 
-        let (result_exprs, result_label_table) = parse(text, 0)?;
+        let (result_sections, result_label_table) = parse(text)?;
+        let result_exprs = &result_sections[0].exprs;
         let expect_label_table: Vec<Label> = vec![
             Label {
                 name: "start".to_string(),
+                section: ".text".to_string(),
                 address: 0,
+                binding: Binding::Local,
             },
             Label {
                 name: "end".to_string(),
+                section: ".text".to_string(),
                 address: 30,
+                binding: Binding::Local,
             },
         ];
         let expect_exprs: Vec<Expr> = vec![
@@ -787,7 +1117,7 @@ mod test {
             },
         ];
         assert_eq!(result_label_table, expect_label_table);
-        assert_eq!(result_exprs, expect_exprs);
+        assert_eq!(result_exprs, &expect_exprs);
 
         Ok(())
     }
@@ -796,15 +1126,20 @@ mod test {
     fn can_parse_c2_inst() -> Result<()> {
         let text = load_test_asm("test/asm/c2_inst_test.asm");
 
-        let (result_exprs, result_label_table) = parse(text, 0)?;
+        let (result_sections, result_label_table) = parse(text)?;
+        let result_exprs = &result_sections[0].exprs;
         let expect_label_table: Vec<Label> = vec![
             Label {
                 name: "start".to_string(),
+                section: ".text".to_string(),
                 address: 0,
+                binding: Binding::Local,
             },
             Label {
                 name: "end".to_string(),
+                section: ".text".to_string(),
                 address: 6,
+                binding: Binding::Local,
             },
         ];
         let expect_exprs: Vec<Expr> = vec![
@@ -837,7 +1172,7 @@ mod test {
             },
         ];
         assert_eq!(result_label_table, expect_label_table);
-        assert_eq!(result_exprs, expect_exprs);
+        assert_eq!(result_exprs, &expect_exprs);
 
         Ok(())
     }
@@ -846,15 +1181,20 @@ mod test {
     fn can_parse_trap_inst() -> Result<()> {
         let text = load_test_asm("test/asm/trap_inst_test.asm");
 
-        let (result_exprs, result_label_table) = parse(text, 0)?;
+        let (result_sections, result_label_table) = parse(text)?;
+        let result_exprs = &result_sections[0].exprs;
         let expect_label_table: Vec<Label> = vec![
             Label {
                 name: "start".to_string(),
+                section: ".text".to_string(),
                 address: 0,
+                binding: Binding::Local,
             },
             Label {
                 name: "end".to_string(),
+                section: ".text".to_string(),
                 address: 2,
+                binding: Binding::Local,
             },
         ];
         let expect_exprs: Vec<Expr> = vec![Expr::Inst {
@@ -867,7 +1207,7 @@ mod test {
             address: 0,
         }];
         assert_eq!(result_label_table, expect_label_table);
-        assert_eq!(result_exprs, expect_exprs);
+        assert_eq!(result_exprs, &expect_exprs);
 
         Ok(())
     }
@@ -875,7 +1215,8 @@ mod test {
     #[test]
     fn can_parse_directive() -> Result<()> {
         let text = load_test_asm("test/asm/directive_test.asm");
-        let (result_exprs, result_label_table) = parse(text, 0)?;
+        let (result_sections, result_label_table) = parse(text)?;
+        let result_exprs = &result_sections[0].exprs;
         let expect_exprs: Vec<Expr> = vec![
             Expr::Const {
                 val: "ffff".to_string(),
@@ -909,19 +1250,137 @@ mod test {
         let expect_label_table: Vec<Label> = vec![
             Label {
                 name: "word".to_string(),
+                section: ".text".to_string(),
                 address: 0,
+                binding: Binding::Local,
             },
             Label {
                 name: "byte".to_string(),
+                section: ".text".to_string(),
                 address: 2,
+                binding: Binding::Local,
             },
         ];
-        assert_eq!(result_exprs, expect_exprs);
+        assert_eq!(result_exprs, &expect_exprs);
         assert_eq!(result_label_table, expect_label_table);
 
         Ok(())
     }
 
+    #[test]
+    fn can_parse_equ() -> Result<()> {
+        let text = load_test_asm("test/asm/equ_test.asm");
+        let (_, result_label_table) = parse(text)?;
+        let expect_label_table: Vec<Label> = vec![
+            Label {
+                name: "MAILBOX".to_string(),
+                section: "".to_string(),
+                address: 0x8010,
+                binding: Binding::Local,
+            },
+            Label {
+                name: "start".to_string(),
+                section: ".text".to_string(),
+                address: 0,
+                binding: Binding::Local,
+            },
+        ];
+        assert_eq!(result_label_table, expect_label_table);
+        assert!(result_label_table[0].is_constant());
+        assert!(!result_label_table[1].is_constant());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_parse_section() -> Result<()> {
+        let text = load_test_asm("test/asm/section_test.asm");
+        let (result_sections, result_label_table) = parse(text)?;
+        let expect_lines: Vec<Line> = vec![
+            Line {
+                address: 0,
+                size: 2,
+                kind: LineKind::Inst,
+                file: String::new(),
+                line_num: 3,
+                column: 2,
+                text: "lil x1, msg@l".to_string(),
+            },
+            Line {
+                address: 2,
+                size: 2,
+                kind: LineKind::Inst,
+                file: String::new(),
+                line_num: 4,
+                column: 2,
+                text: "lih x1, msg@h".to_string(),
+            },
+            Line {
+                address: 4,
+                size: 2,
+                kind: LineKind::Inst,
+                file: String::new(),
+                line_num: 16,
+                column: 2,
+                text: "jal zero, start".to_string(),
+            },
+        ];
+        assert_eq!(result_sections[0].lines, expect_lines);
+        let result_sections: Vec<(&str, SectionKind, u16, usize)> = result_sections
+            .iter()
+            .map(|s| (s.name.as_str(), s.kind.clone(), s.size, s.exprs.len()))
+            .collect();
+        let expect_sections = vec![
+            (".text", SectionKind::Progbits, 6, 3),
+            (".data", SectionKind::Progbits, 2, 1),
+            (".bss", SectionKind::Nobits, 16, 1),
+            ("vectors", SectionKind::Progbits, 2, 2),
+        ];
+        let expect_label_table: Vec<Label> = vec![
+            Label {
+                name: "start".to_string(),
+                section: ".text".to_string(),
+                address: 0,
+                binding: Binding::Local,
+            },
+            Label {
+                name: "msg".to_string(),
+                section: ".data".to_string(),
+                address: 0,
+                binding: Binding::Local,
+            },
+            Label {
+                name: "buf".to_string(),
+                section: ".bss".to_string(),
+                address: 0,
+                binding: Binding::Local,
+            },
+            Label {
+                name: "reset".to_string(),
+                section: "vectors".to_string(),
+                address: 0,
+                binding: Binding::Local,
+            },
+        ];
+        assert_eq!(result_sections, expect_sections);
+        assert_eq!(result_label_table, expect_label_table);
+        assert!(parse(".bss\n\t.word 0x1234".to_string()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cannot_overflow_section() -> Result<()> {
+        let err = parse(".org 0xFFFC\n\ttrap\n\ttrap\n".to_string()).unwrap_err();
+        assert_eq!(err.to_string(), "section .text overflows : line 3:\ttrap");
+        assert!(parse(".org 0xFFFE\n\ttrap\n\ttrap\n".to_string()).is_err());
+        assert!(parse(".org 0xFFFF\n\t.word 0x1234\n".to_string()).is_err());
+        assert!(parse(".org 0xFFFF\n\t.space 1\n".to_string()).is_err());
+        assert!(parse(".org 0xFFFD\n\t.byte 0x12\n".to_string()).is_ok());
+
+        Ok(())
+    }
+
     fn load_test_asm(path: &str) -> String {
         let file = std::fs::File::open(path).unwrap();
         let mut reader = BufReader::new(file);
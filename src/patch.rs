@@ -0,0 +1,198 @@
+use crate::file;
+use crate::format::{self, Endian, Format, FormatArgs};
+use crate::gen;
+use crate::image::Image;
+use crate::layout::{self, Layout};
+use crate::map;
+use crate::parse::{self, Binding, Label};
+use anyhow::{anyhow, Context, Result};
+use clap_num::maybe_hex;
+use std::path::PathBuf;
+
+/// Options of `zktc-asm patch`.
+#[derive(clap::Args)]
+pub struct PatchArgs {
+    /// .asm file with the instructions to patch in
+    pub snippet: PathBuf,
+
+    /// image to patch
+    #[arg(long = "image")]
+    pub image: PathBuf,
+
+    /// image format [default: by the extension of the image file]
+    #[arg(short = 'I', value_enum)]
+    pub image_format: Option<Format>,
+
+    /// symbol map of the image, written with -m
+    #[arg(short = 'm')]
+    pub map_file_name: Option<PathBuf>,
+
+    /// label or address to patch at
+    #[arg(long = "at")]
+    pub at: String,
+
+    /// bytes that may be overwritten [default: the size of the label in the map]
+    #[arg(long = "size", value_parser=maybe_hex::<u16>)]
+    pub size: Option<u16>,
+
+    /// address of the first byte of mem and binary images
+    #[arg(long = "load-address", default_value_t = 0, value_parser=maybe_hex::<u16>)]
+    pub load_address: u16,
+
+    /// bits per word of verilog, coe and mif images
+    #[arg(long = "word-width", default_value_t = 8, value_parser = format::parse_word_width)]
+    pub word_width: u8,
+
+    /// how bytes are packed into words
+    #[arg(long = "endian", value_enum, default_value_t = Endian::Little)]
+    pub endian: Endian,
+
+    /// output file name, or - for stdout [default: overwrite the image]
+    #[arg(short = 'o')]
+    pub output_file_name: Option<PathBuf>,
+}
+
+/// Returns the address and the size of the region starting at `at`, a label
+/// of `symbols` or an address.
+pub fn region(at: &str, size: Option<u16>, symbols: &[map::Symbol]) -> Result<(u16, u16)> {
+    let symbol = symbols
+        .iter()
+        .find(|s| s.name == at && s.kind == map::SymbolKind::Label);
+    let address = match (symbol, parse::number(at)) {
+        (Some(symbol), _) => symbol.address,
+        (None, Ok(("", address))) if address <= 0xFFFF => address as u16,
+        _ => return Err(anyhow!("unknown label {}", at)),
+    };
+    let size = size
+        .or_else(|| {
+            symbols
+                .iter()
+                .find(|s| s.address == address && s.kind == map::SymbolKind::Label)
+                .map(|s| s.size)
+        })
+        .ok_or_else(|| anyhow!("size of the region at {} is unknown, use --size", at))?;
+    Ok((address, size))
+}
+
+/// Assembles `text` at `address` with the symbols of the image visible, and
+/// checks that the result stays within `size` bytes.
+pub fn assemble(text: String, address: u16, size: u16, symbols: &[map::Symbol]) -> Result<Image> {
+    let (mut sections, mut label_table) = parse::parse(text)?;
+    layout::place(&Layout::new(address), &mut sections, &mut label_table)?;
+    // image symbols are absolute, so they are added after placing
+    for symbol in symbols {
+        label_table.push(Label {
+            name: symbol.name.clone(),
+            section: "".to_string(),
+            address: symbol.address,
+            binding: Binding::Local,
+        });
+    }
+    let patch = gen::gen(sections, label_table)?;
+
+    let end = address as u32 + size as u32;
+    if patch.start() < address || patch.end() > end {
+        return Err(anyhow!(
+            "patch at 0x{:04x}-0x{:04x} does not fit in the {} bytes at 0x{:04x}",
+            patch.start(),
+            patch.end().saturating_sub(1),
+            size,
+            address
+        ));
+    }
+    Ok(patch)
+}
+
+/// Patches the image and writes it back in its own format, with the entry,
+/// word width, depth and radix the file was written with.
+pub fn patch(args: &PatchArgs) -> Result<()> {
+    let image_format = match args.image_format {
+        Some(format) => format,
+        None => args
+            .image
+            .extension()
+            .and_then(|e| Format::from_extension(&e.to_string_lossy()))
+            .ok_or_else(|| anyhow!("unknown image format, use -I to select it"))?,
+    };
+    let output_file_name = args.output_file_name.clone().unwrap_or(args.image.clone());
+    file::check_std(
+        [args.snippet.as_path(), args.image.as_path()],
+        [output_file_name.as_path()],
+    )?;
+
+    let data = file::read_bytes(&args.image)?;
+    let (mut image, info) = format::read_with_info(
+        &data,
+        image_format,
+        args.load_address,
+        args.endian,
+        args.word_width,
+    )?;
+    let symbols = match &args.map_file_name {
+        Some(path) => map::read(&file::read(path)?)
+            .with_context(|| format!("could not load map '{}'", path.display()))?,
+        None => Vec::new(),
+    };
+
+    let (address, size) = region(&args.at, args.size, &symbols)?;
+    let text = file::read(&args.snippet)?;
+    let patch = assemble(text, address, size, &symbols)
+        .with_context(|| format!("could not assemble '{}'", args.snippet.display()))?;
+    for segment in &patch.segments {
+        image.write(segment.address, &segment.bytes);
+    }
+
+    let mut format_args = FormatArgs::new(image_format);
+    format_args.word_width = args.word_width;
+    format_args.endian = args.endian;
+    if matches!(image_format, Format::Mem | Format::Binary) {
+        format_args.start_address = Some(args.load_address);
+    }
+    if matches!(image_format, Format::Coe | Format::Mif | Format::Verilog) {
+        format_args.start_address = Some(0);
+    }
+    info.apply(&mut format_args)
+        .with_context(|| format!("could not write '{}' back", args.image.display()))?;
+    let name = info.name.unwrap_or_else(|| file::module_name(&args.image));
+    format::write_file(&image, &[], &[], &name, &format_args, &output_file_name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn can_assemble_patch() -> Result<()> {
+        let symbols = vec![
+            map::Symbol {
+                name: "fix".to_string(),
+                address: 0x0010,
+                section: ".text".to_string(),
+                size: 4,
+                kind: map::SymbolKind::Label,
+                binding: Binding::Local,
+            },
+            map::Symbol {
+                name: "putc".to_string(),
+                address: 0x0040,
+                section: ".text".to_string(),
+                size: 8,
+                kind: map::SymbolKind::Label,
+                binding: Binding::Global,
+            },
+        ];
+        assert_eq!(region("fix", None, &symbols)?, (0x0010, 4));
+        assert_eq!(region("0x0040", None, &symbols)?, (0x0040, 8));
+        assert_eq!(region("0x20", Some(2), &symbols)?, (0x0020, 2));
+        assert!(region("0x20", None, &symbols).is_err());
+        assert!(region("missing", Some(2), &symbols).is_err());
+
+        let patch = assemble("\tjal ra, putc\n".to_string(), 0x0010, 4, &symbols)?;
+        assert_eq!(patch.start(), 0x0010);
+        assert_eq!(patch.to_bytes(0), vec![0x30, 0x30]);
+
+        let text = "\tjal ra, putc\n\tjal ra, putc\n\tjal ra, putc\n".to_string();
+        assert!(assemble(text, 0x0010, 4, &symbols).is_err());
+        Ok(())
+    }
+}
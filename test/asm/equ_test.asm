@@ -0,0 +1,5 @@
+// Equ Test
+.equ MAILBOX, 0x8010
+start:	// address 0
+	lil x1, MAILBOX@l
+	lih x1, MAILBOX@h
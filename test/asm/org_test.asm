@@ -0,0 +1,6 @@
+// Org Test
+start:	// address 0
+	trap
+	.org 0x10
+vector:	// address 16
+	.byte 0x11
@@ -0,0 +1,12 @@
+// Relocation Test
+.extern putc
+.global start
+start:	// address 0
+	beq a0, zero, end
+	jal ra, putc
+	lil a0, msg@l
+end:	// address 6
+	jal zero, start
+.data
+msg:	// .data 0
+	.byte 0x41
@@ -0,0 +1,16 @@
+// Section Test
+start:	// .text 0
+	lil x1, msg@l
+	lih x1, msg@h
+.data
+msg:	// .data 0
+	.word 0x6c6c
+.bss
+buf:	// .bss 0
+	.space 0x10
+.section vectors
+reset:	// vectors 0
+	.byte 0x01
+	.space 1
+.text
+	jal zero, start